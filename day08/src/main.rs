@@ -1,151 +1,181 @@
 //! Day 8
 
-use std::collections::HashSet;
-
 use util::*;
 
-fn parse(input: &str) -> Result<Vec<Vec<u32>>, Error> {
-    input
-        .lines()
-        .map(|line| {
-            line.chars()
-                .map(|c| c.to_digit(10).context("Non-digit input"))
-                .collect()
-        })
-        .collect()
+/// A rectangular grid of tree heights
+struct Forest<H> {
+    width: usize,
+    height: usize,
+    /// Heights stored row by row
+    trees: Vec<H>,
 }
 
-fn part1(trees: &[Vec<u32>]) -> Result<usize, Error> {
-    let mut seen: HashSet<(usize, usize)> = HashSet::new();
-
-    // Rows
-    for (y, row) in trees.iter().enumerate() {
-        let mut max: Option<u32> = None;
-        for (x, tree) in row.iter().enumerate() {
-            if max.map_or(true, |m| *tree > m) {
-                seen.insert((x, y));
-                max = Some(*tree);
-            }
-        }
-        max = None;
-        for (x, tree) in row.iter().enumerate().rev() {
-            if max.map_or(true, |m| *tree > m) {
-                seen.insert((x, y));
-                max = Some(*tree);
-            }
+impl<H: Ord> Forest<H> {
+    fn new(rows: Vec<Vec<H>>) -> Result<Self, Error> {
+        let width = rows.first().context("Trees empty")?.len();
+        let height = rows.len();
+        if let Some(y) = rows.iter().position(|row| row.len() != width) {
+            bail!(
+                "Grid not rectangular: row {} has {} trees, expected {}",
+                y,
+                rows[y].len(),
+                width
+            );
         }
+
+        Ok(Forest {
+            width,
+            height,
+            trees: rows.into_iter().flatten().collect(),
+        })
     }
 
-    // Columns
-    for x in 0..trees.first().context("Trees empty")?.len() {
-        let mut max: Option<u32> = None;
-        for (y, row) in trees.iter().enumerate() {
-            let tree = row.get(x).context("Grid not rectangular")?;
-            if max.map_or(true, |m| *tree > m) {
-                seen.insert((x, y));
-                max = Some(*tree);
-            }
-        }
-        max = None;
-        for (y, row) in trees.iter().enumerate().rev() {
-            let tree = row.get(x).context("Grid still not rectangular")?;
-            if max.map_or(true, |m| *tree > m) {
-                seen.insert((x, y));
-                max = Some(*tree);
-            }
+    /// The indices of each line of trees, ordered so a tree looking in `dir` sees the trees
+    /// before it from nearest to furthest
+    fn lines(&self, dir: Direction) -> Vec<Vec<usize>> {
+        let (width, height) = (self.width, self.height);
+        match dir {
+            Direction::Left => (0..height)
+                .map(|y| (0..width).map(|x| y * width + x).collect())
+                .collect(),
+            Direction::Right => (0..height)
+                .map(|y| (0..width).rev().map(|x| y * width + x).collect())
+                .collect(),
+            Direction::Up => (0..width)
+                .map(|x| (0..height).map(|y| y * width + x).collect())
+                .collect(),
+            Direction::Down => (0..width)
+                .map(|x| (0..height).rev().map(|y| y * width + x).collect())
+                .collect(),
         }
     }
 
-    Ok(seen.len())
-}
-
-fn part2(trees: &[Vec<u32>]) -> Result<usize, Error> {
-    let mut max = 0;
-
-    for (y, row) in trees.iter().enumerate() {
-        for (x, treehouse) in row.iter().enumerate() {
-            let mut score = 1;
-            let mut looking = x;
-            let mut distance = 0;
-
-            // Left
-            while looking > 0 {
-                looking -= 1;
-                distance += 1;
-
-                let tree = row.get(looking).context("Out of bounds to left")?;
-                if tree >= treehouse {
-                    break;
+    /// Work out what every tree sees when looking in `dir`, indexed like `trees`.
+    ///
+    /// Each line keeps a stack of trees that could still block the view, tallest at the bottom.
+    /// Shorter trees are popped as they get hidden behind a new one, so each tree is pushed and
+    /// popped at most once.
+    fn scan(&self, dir: Direction) -> Vec<Sight> {
+        let mut sights = vec![
+            Sight {
+                distance: 0,
+                from_edge: true,
+            };
+            self.trees.len()
+        ];
+
+        for line in self.lines(dir) {
+            let mut blockers: Vec<usize> = vec![];
+            for (pos, &idx) in line.iter().enumerate() {
+                let tree = &self.trees[idx];
+                while blockers
+                    .last()
+                    .is_some_and(|&b| self.trees[line[b]] < *tree)
+                {
+                    blockers.pop();
                 }
-            }
-
-            score *= distance;
-            looking = x;
-            distance = 0;
 
-            // Right
-            while looking < row.len() - 1 {
-                looking += 1;
-                distance += 1;
-
-                let tree = row.get(looking).context("Out of bounds to right")?;
-                if tree >= treehouse {
-                    break;
-                }
+                sights[idx] = match blockers.last() {
+                    Some(&b) => Sight {
+                        distance: pos - b,
+                        from_edge: false,
+                    },
+                    None => Sight {
+                        distance: pos,
+                        from_edge: true,
+                    },
+                };
+                blockers.push(pos);
             }
+        }
 
-            score *= distance;
-            looking = y;
-            distance = 0;
-
-            // Up
-            while looking > 0 {
-                looking -= 1;
-                distance += 1;
+        sights
+    }
 
-                let tree = trees
-                    .get(looking)
-                    .context("Out of bounds to top")?
-                    .get(x)
-                    .context("Hole in grid")?;
+    /// How many trees can be seen from outside the grid
+    fn visible(&self) -> usize {
+        let scans: Vec<Vec<Sight>> = Direction::ALL.iter().map(|&d| self.scan(d)).collect();
+        (0..self.trees.len())
+            .filter(|&i| scans.iter().any(|scan| scan[i].from_edge))
+            .count()
+    }
 
-                if tree >= treehouse {
-                    break;
-                }
+    /// The scenic score of every tree, indexed like `trees`
+    fn scenic_scores(&self) -> Vec<usize> {
+        let mut scores = vec![1; self.trees.len()];
+        for dir in Direction::ALL {
+            for (score, sight) in scores.iter_mut().zip(self.scan(dir)) {
+                *score *= sight.distance;
             }
+        }
+        scores
+    }
+}
 
-            score *= distance;
-            looking = y;
-            distance = 0;
+/// A direction a tree can be looked at from
+#[derive(Clone, Copy)]
+enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
 
-            // Down
-            while looking < trees.len() - 1 {
-                looking += 1;
-                distance += 1;
+impl Direction {
+    const ALL: [Direction; 4] = [
+        Direction::Left,
+        Direction::Right,
+        Direction::Up,
+        Direction::Down,
+    ];
+}
 
-                let tree = trees
-                    .get(looking)
-                    .context("Out of bounds to bottom")?
-                    .get(x)
-                    .context("Hole in grid")?;
+/// What a tree sees looking in one direction
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Sight {
+    /// How many trees can be seen before the view is blocked or the edge is reached
+    distance: usize,
+    /// Whether every tree in this direction is shorter, so it can be seen from the edge
+    from_edge: bool,
+}
 
-                if tree >= treehouse {
-                    break;
+/// Parse a grid of single digits, or of whitespace separated numbers for taller trees
+fn parse(input: &str) -> Result<Forest<u32>, Error> {
+    Forest::new(
+        input
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                if line.trim().contains(char::is_whitespace) {
+                    line.split_whitespace()
+                        .map(|n| n.parse().with_context(|| format!("Invalid height {}", n)))
+                        .collect()
+                } else {
+                    line.trim()
+                        .chars()
+                        .map(|c| c.to_digit(10).context("Non-digit input"))
+                        .collect()
                 }
-            }
-
-            score *= distance;
+            })
+            .collect::<Result<_, Error>>()?,
+    )
+}
 
-            max = max.max(score);
-        }
-    }
+fn part1<H: Ord>(trees: &Forest<H>) -> usize {
+    trees.visible()
+}
 
-    Ok(max)
+fn part2<H: Ord>(trees: &Forest<H>) -> Result<usize, Error> {
+    trees
+        .scenic_scores()
+        .into_iter()
+        .max()
+        .context("Trees empty")
 }
 
 fn main() -> Result<(), Error> {
     let trees = parse(&read_stdin()?)?;
-    println!("Part 1: {}", part1(&trees)?);
+    println!("Part 1: {}", part1(&trees));
     println!("Part 2: {}", part2(&trees)?);
     Ok(())
 }
@@ -158,7 +188,7 @@ mod tests {
 
     #[test]
     fn part1_example() -> Result<(), Error> {
-        assert_eq!(part1(&parse(SAMPLE)?)?, 21);
+        assert_eq!(part1(&parse(SAMPLE)?), 21);
         Ok(())
     }
 
@@ -167,4 +197,52 @@ mod tests {
         assert_eq!(part2(&parse(SAMPLE)?)?, 8);
         Ok(())
     }
+
+    #[test]
+    fn other_heights() -> Result<(), Error> {
+        let spaced = SAMPLE
+            .lines()
+            .map(|line| {
+                line.chars()
+                    .map(|c| format!("{}", c.to_digit(10).unwrap() * 100))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let tall = parse(&spaced)?;
+        assert_eq!(tall.trees[0], 300);
+        assert_eq!(part1(&tall), 21);
+        assert_eq!(part2(&tall)?, 8);
+
+        let letters = Forest::new(
+            SAMPLE
+                .lines()
+                .map(|line| {
+                    line.chars()
+                        .map(|c| (b'a' + c as u8 - b'0') as char)
+                        .collect()
+                })
+                .collect(),
+        )?;
+        assert_eq!(part1(&letters), 21);
+        assert_eq!(part2(&letters)?, 8);
+
+        assert!(parse("123\n45").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn scan_matches_every_direction() -> Result<(), Error> {
+        let trees = parse(SAMPLE)?;
+        // The middle 5 in the fourth row, from the puzzle's example
+        let idx = 3 * trees.width + 2;
+        let distances: Vec<usize> = Direction::ALL
+            .iter()
+            .map(|&d| trees.scan(d)[idx].distance)
+            .collect();
+        assert_eq!(distances, vec![2, 2, 2, 1]);
+        assert_eq!(trees.scenic_scores()[idx], 8);
+        Ok(())
+    }
 }
@@ -1,6 +1,6 @@
 //! Day 9
 
-use std::{cmp::Ordering, collections::HashSet};
+use std::{collections::HashSet, env};
 
 use point_2d::Point2D;
 use util::*;
@@ -27,111 +27,398 @@ fn parse(input: &str) -> Result<Vec<(Point2D<isize>, isize)>, Error> {
         .collect()
 }
 
-/// According to the provided rules, have a trailing segment of rope follow a leading segment
-fn move_follower(head: &mut Point2D<isize>, tail: &mut Point2D<isize>) {
-    if head.0 - tail.0 >= 2 {
-        tail.0 += 1;
-        tail.1 += match tail.1.cmp(&head.1) {
-            Ordering::Less => 1,
-            Ordering::Greater => -1,
-            _ => 0,
+/// According to the provided rules, have a trailing segment of rope follow a leading segment.
+/// If the leader is no longer touching, the follower steps one cell towards it on each axis.
+fn move_follower(head: Point2D<isize>, tail: &mut Point2D<isize>) {
+    let gap = head - *tail;
+    if gap.0.abs() > 1 || gap.1.abs() > 1 {
+        *tail += Point2D(gap.0.signum(), gap.1.signum());
+    }
+}
+
+/// Which knots a rope should record the visited cells of
+enum Track {
+    Knot(usize),
+    All,
+}
+
+/// A rope made of knots, each following the one in front of it
+struct Rope {
+    knots: Vec<Point2D<isize>>,
+    /// The cells visited by each knot, if that knot is tracked
+    trails: Vec<Option<HashSet<Point2D<isize>>>>,
+}
+
+impl Rope {
+    /// Create a rope of `length` knots all starting at the origin
+    fn new(length: usize, track: Track) -> Result<Self, Error> {
+        if length == 0 {
+            bail!("A rope needs at least one knot");
         }
+
+        let knots = vec![Point2D(0, 0); length];
+        let mut trails: Vec<Option<HashSet<Point2D<isize>>>> = vec![None; length];
+        match track {
+            Track::Knot(k) => {
+                *trails.get_mut(k).with_context(|| {
+                    format!("Can't track knot {} on a rope of {} knots", k, length)
+                })? = Some(HashSet::new());
+            }
+            Track::All => trails.fill(Some(HashSet::new())),
+        }
+
+        let mut rope = Rope { knots, trails };
+        rope.record();
+        Ok(rope)
     }
 
-    if head.0 - tail.0 <= -2 {
-        tail.0 -= 1;
-        tail.1 += match tail.1.cmp(&head.1) {
-            Ordering::Less => 1,
-            Ordering::Greater => -1,
-            _ => 0,
+    /// Mark where each tracked knot currently is
+    fn record(&mut self) {
+        for (knot, trail) in self.knots.iter().zip(self.trails.iter_mut()) {
+            if let Some(trail) = trail {
+                trail.insert(*knot);
+            }
         }
     }
 
-    if head.1 - tail.1 >= 2 {
-        tail.1 += 1;
-        tail.0 += match tail.0.cmp(&head.0) {
-            Ordering::Less => 1,
-            Ordering::Greater => -1,
-            _ => 0,
+    /// Move the head one step in a direction and let the rest of the rope follow
+    fn step(&mut self, direction: Point2D<isize>) {
+        self.knots[0] += direction;
+
+        for back_idx in 1..self.knots.len() {
+            let front = self.knots[back_idx - 1];
+            move_follower(front, &mut self.knots[back_idx]);
         }
+
+        self.record();
     }
 
-    if head.1 - tail.1 <= -2 {
-        tail.1 -= 1;
-        tail.0 += match tail.0.cmp(&head.0) {
-            Ordering::Less => 1,
-            Ordering::Greater => -1,
-            _ => 0,
+    /// Apply a list of moves, one step at a time
+    fn apply(&mut self, moves: &[(Point2D<isize>, isize)]) {
+        for (dir, distance) in moves.iter().copied() {
+            for _ in 0..distance {
+                self.step(dir);
+            }
         }
     }
-}
 
-fn part1(moves: &[(Point2D<isize>, isize)]) -> usize {
-    let mut points: HashSet<Point2D<isize>> = HashSet::new();
+    /// How many cells knot `k` has visited, or `None` if it isn't tracked
+    fn visited(&self, k: usize) -> Option<usize> {
+        self.trails.get(k)?.as_ref().map(HashSet::len)
+    }
 
-    let mut head: Point2D<isize> = Point2D(0, 0);
-    let mut tail = head;
+    fn tail(&self) -> usize {
+        self.knots.len() - 1
+    }
+}
 
-    for (dir, distance) in moves.iter().copied() {
-        for _ in 0..distance {
-            head += dir;
+/// The smallest and largest coordinates a rope reached
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bounds {
+    min: Point2D<isize>,
+    max: Point2D<isize>,
+}
+
+impl Bounds {
+    /// Find the box containing every cell visited by any knot of a rope of `length` knots
+    fn of_moves(moves: &[(Point2D<isize>, isize)], length: usize) -> Result<Self, Error> {
+        let mut rope = Rope::new(length, Track::All)?;
+        rope.apply(moves);
+        let cells = rope.trails.iter().flatten().flatten();
+        Ok(Bounds {
+            min: Point2D(
+                cells.clone().map(|p| p.0).min().unwrap_or(0),
+                cells.clone().map(|p| p.1).min().unwrap_or(0),
+            ),
+            max: Point2D(
+                cells.clone().map(|p| p.0).max().unwrap_or(0),
+                cells.map(|p| p.1).max().unwrap_or(0),
+            ),
+        })
+    }
 
-            move_follower(&mut head, &mut tail);
+    /// Draw the box top row first, picking a character for each cell
+    fn draw(&self, cell: impl Fn(Point2D<isize>) -> char) -> String {
+        (self.min.1..=self.max.1)
+            .rev()
+            .map(|y| {
+                (self.min.0..=self.max.0)
+                    .map(|x| cell(Point2D(x, y)))
+                    .chain(std::iter::once('\n'))
+                    .collect::<String>()
+            })
+            .collect()
+    }
+}
 
-            points.insert(tail);
+impl Rope {
+    /// The label the puzzle uses for knot `k`: H for the head, then T for a two knot tail,
+    /// otherwise the knot's number
+    fn label(&self, k: usize) -> char {
+        match k {
+            0 => 'H',
+            1 if self.knots.len() == 2 => 'T',
+            k => char::from_digit(k as u32, 36).unwrap_or('*'),
         }
     }
 
-    points.len()
+    /// Draw the knots within `bounds`, with knots nearer the head drawn on top and `s` marking
+    /// the start
+    fn render(&self, bounds: &Bounds) -> String {
+        bounds.draw(
+            |cell| match self.knots.iter().position(|knot| *knot == cell) {
+                Some(k) => self.label(k),
+                None if cell == Point2D(0, 0) => 's',
+                None => '.',
+            },
+        )
+    }
+
+    /// Draw the cells visited by knot `k` within `bounds`, with `s` marking the start
+    fn render_visited(&self, k: usize, bounds: &Bounds) -> Result<String, Error> {
+        let trail = self
+            .trails
+            .get(k)
+            .and_then(Option::as_ref)
+            .with_context(|| format!("Knot {} wasn't tracked", k))?;
+        Ok(bounds.draw(|cell| {
+            if cell == Point2D(0, 0) {
+                's'
+            } else if trail.contains(&cell) {
+                '#'
+            } else {
+                '.'
+            }
+        }))
+    }
 }
 
-fn part2(moves: &[(Point2D<isize>, isize)]) -> usize {
-    let mut rope: Vec<Point2D<isize>> = (0..10).map(|_| Point2D::default()).collect();
-    let mut points: HashSet<Point2D<isize>> = HashSet::new();
+/// When to draw the rope while tracing
+#[derive(Clone, Copy, PartialEq)]
+enum Show {
+    EveryMove,
+    End,
+}
 
+/// The letter used for a direction in the puzzle input
+fn direction_name(dir: Point2D<isize>) -> char {
+    match dir {
+        Point2D(-1, 0) => 'L',
+        Point2D(1, 0) => 'R',
+        Point2D(0, -1) => 'D',
+        _ => 'U',
+    }
+}
+
+/// Draw a rope of `length` knots as the puzzle does, followed by the cells its tail visited
+fn trace(moves: &[(Point2D<isize>, isize)], length: usize, show: Show) -> Result<String, Error> {
+    let bounds = Bounds::of_moves(moves, length)?;
+    let mut rope = Rope::new(length, Track::Knot(length - 1))?;
+    let mut out = String::new();
+
+    if show == Show::EveryMove {
+        out += &format!("== Initial State ==\n\n{}\n", rope.render(&bounds));
+    }
     for (dir, distance) in moves.iter().copied() {
         for _ in 0..distance {
-            rope[0] += dir;
-
-            for back_idx in 1..=9 {
-                let front_idx = back_idx - 1;
-                let mut pair = rope[front_idx..=back_idx].iter_mut();
-                let front = pair.next().unwrap();
-                let back = pair.next().unwrap();
-                move_follower(front, back);
-            }
-
-            points.insert(rope[9]);
+            rope.step(dir);
+        }
+        if show == Show::EveryMove {
+            out += &format!(
+                "== {} {} ==\n\n{}\n",
+                direction_name(dir),
+                distance,
+                rope.render(&bounds)
+            );
         }
     }
+    if show == Show::End {
+        out += &format!("{}\n", rope.render(&bounds));
+    }
+    out += &rope.render_visited(rope.tail(), &bounds)?;
+    Ok(out)
+}
 
-    points.len()
+/// Count the cells visited by the tail of a rope of `length` knots
+fn tail_visits(moves: &[(Point2D<isize>, isize)], length: usize) -> Result<usize, Error> {
+    let mut rope = Rope::new(length, Track::Knot(length.saturating_sub(1)))?;
+    rope.apply(moves);
+    rope.visited(rope.tail())
+        .context("Tail of the rope wasn't tracked")
+}
+
+fn part1(moves: &[(Point2D<isize>, isize)]) -> Result<usize, Error> {
+    tail_visits(moves, 2)
+}
+
+fn part2(moves: &[(Point2D<isize>, isize)]) -> Result<usize, Error> {
+    tail_visits(moves, 10)
 }
 
 fn main() -> Result<(), Error> {
     let moves = parse(&read_stdin()?)?;
-    println!("Part 1: {}", part1(&moves));
-    println!("Part 2: {}", part2(&moves));
+    println!("Part 1: {}", part1(&moves)?);
+    println!("Part 2: {}", part2(&moves)?);
+
+    let args: Vec<String> = env::args().collect();
+    let trace_flag = args
+        .iter()
+        .position(|a| a == "--trace")
+        .map(|i| (i, Show::EveryMove))
+        .or_else(|| {
+            args.iter()
+                .position(|a| a == "--map")
+                .map(|i| (i, Show::End))
+        });
+    if let Some((i, show)) = trace_flag {
+        let length = match args.get(i + 1) {
+            Some(n) => n
+                .parse()
+                .context("Invalid knot count for --trace or --map")?,
+            None => 10,
+        };
+        print!("{}", trace(&moves, length, show)?);
+    }
+
+    if let Some(i) = args.iter().position(|a| a == "--trails") {
+        let length = match args.get(i + 1) {
+            Some(n) => n.parse().context("Invalid knot count for --trails")?,
+            None => 10,
+        };
+        let mut rope = Rope::new(length, Track::All)?;
+        rope.apply(&moves);
+        for k in 0..length {
+            if let Some(visited) = rope.visited(k) {
+                println!("Knot {}: {} cells", k, visited);
+            }
+        }
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cmp::Ordering;
 
     const SAMPLE: &str = include_str!("./sample");
     const SAMPLE2: &str = include_str!("./sample2");
 
+    /// The original rules, kept to check the simpler version against
+    fn old_move_follower(head: &mut Point2D<isize>, tail: &mut Point2D<isize>) {
+        if head.0 - tail.0 >= 2 {
+            tail.0 += 1;
+            tail.1 += match tail.1.cmp(&head.1) {
+                Ordering::Less => 1,
+                Ordering::Greater => -1,
+                _ => 0,
+            }
+        }
+
+        if head.0 - tail.0 <= -2 {
+            tail.0 -= 1;
+            tail.1 += match tail.1.cmp(&head.1) {
+                Ordering::Less => 1,
+                Ordering::Greater => -1,
+                _ => 0,
+            }
+        }
+
+        if head.1 - tail.1 >= 2 {
+            tail.1 += 1;
+            tail.0 += match tail.0.cmp(&head.0) {
+                Ordering::Less => 1,
+                Ordering::Greater => -1,
+                _ => 0,
+            }
+        }
+
+        if head.1 - tail.1 <= -2 {
+            tail.1 -= 1;
+            tail.0 += match tail.0.cmp(&head.0) {
+                Ordering::Less => 1,
+                Ordering::Greater => -1,
+                _ => 0,
+            }
+        }
+    }
+
+    #[test]
+    fn follower_matches_old_rules() {
+        let directions: Vec<Point2D<isize>> = (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |y| Point2D(x, y)))
+            .collect();
+
+        // Every touching leader position, moved one step in every direction
+        for start in directions.iter().copied() {
+            for dir in directions.iter().copied() {
+                let head = start + dir;
+                let mut tail = Point2D(0, 0);
+                move_follower(head, &mut tail);
+
+                let (mut old_head, mut old_tail) = (start + dir, Point2D(0, 0));
+                old_move_follower(&mut old_head, &mut old_tail);
+
+                assert_eq!(tail, old_tail, "leader at {:?} moved {:?}", start, dir);
+                let gap = head - tail;
+                assert!(gap.0.abs() <= 1 && gap.1.abs() <= 1);
+            }
+        }
+    }
+
     #[test]
     fn part1_example() -> Result<(), Error> {
-        assert_eq!(part1(&parse(SAMPLE)?), 13);
+        assert_eq!(part1(&parse(SAMPLE)?)?, 13);
         Ok(())
     }
 
     #[test]
     fn part2_example() -> Result<(), Error> {
-        assert_eq!(part2(&parse(SAMPLE)?), 1);
-        assert_eq!(part2(&parse(SAMPLE2)?), 36);
+        assert_eq!(part2(&parse(SAMPLE)?)?, 1);
+        assert_eq!(part2(&parse(SAMPLE2)?)?, 36);
+        Ok(())
+    }
+
+    #[test]
+    fn every_knot_tracked() -> Result<(), Error> {
+        let mut rope = Rope::new(10, Track::All)?;
+        rope.apply(&parse(SAMPLE2)?);
+        assert_eq!(rope.visited(0), Some(96));
+        assert_eq!(rope.visited(1), Some(88));
+        assert_eq!(rope.visited(9), Some(36));
+        assert_eq!(rope.visited(10), None);
+
+        let mut rope = Rope::new(10, Track::Knot(1))?;
+        rope.apply(&parse(SAMPLE2)?);
+        assert_eq!(rope.visited(1), Some(88));
+        assert_eq!(rope.visited(9), None);
+
+        assert!(Rope::new(2, Track::Knot(2)).is_err());
+        assert!(Rope::new(0, Track::All).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn render_example() -> Result<(), Error> {
+        let moves = parse(SAMPLE)?;
+        assert_eq!(
+            trace(&moves, 2, Show::End)?,
+            "......\n\
+             ......\n\
+             .TH...\n\
+             ......\n\
+             s.....\n\
+             \n\
+             ..##..\n\
+             ...##.\n\
+             .####.\n\
+             ....#.\n\
+             s###..\n"
+        );
+
+        let every_move = trace(&moves, 2, Show::EveryMove)?;
+        assert!(every_move.starts_with("== Initial State ==\n\n......\n"));
+        assert!(every_move.contains("== R 4 ==\n\n......\n......\n......\n......\ns..TH.\n"));
         Ok(())
     }
 }
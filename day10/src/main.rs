@@ -1,65 +1,266 @@
 //! Day 10
 
-use std::{convert::TryInto, fmt::Write};
+use std::fmt::{self, Display};
 
 use util::*;
 
-fn parse(input: &str) -> Result<Vec<Option<isize>>, Error> {
+/// An instruction the CPU can run. Registers are named by a single lowercase letter, so `addx 3`
+/// adds 3 to register `x`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Instruction {
+    Noop,
+    /// Add a value to a register
+    Add(char, isize),
+    /// Multiply a register by a value
+    Mul(char, isize),
+    /// Move forward or back a number of instructions
+    Jump(isize),
+    /// Jump if a register isn't zero
+    JumpIfNonZero(char, isize),
+}
+
+impl Instruction {
+    /// How many cycles the instruction takes to complete
+    fn cycles(&self) -> usize {
+        match self {
+            Instruction::Noop | Instruction::Jump(_) | Instruction::JumpIfNonZero(..) => 1,
+            Instruction::Add(..) | Instruction::Mul(..) => 2,
+        }
+    }
+}
+
+/// Split an opcode like `addx` into its operation and register
+fn register_op(op: &str) -> Option<(&str, char)> {
+    let register = op.chars().last().filter(char::is_ascii_lowercase)?;
+    Some((&op[..op.len() - 1], register))
+}
+
+fn parse(input: &str) -> Result<Vec<Instruction>, Error> {
     input
         .lines()
         .map(|line| {
-            line.split_once(' ') // "noop" will return None
-                .map(|split| match split {
-                    ("addx", val) => val.parse().map_err(Error::from),
-                    _ => bail!("Invalid instruction: {}", line),
-                })
-                .transpose()
+            let (op, arg) = line.split_once(' ').unwrap_or((line, ""));
+            let arg = || -> Result<isize, Error> {
+                arg.parse()
+                    .with_context(|| format!("Invalid argument: {}", line))
+            };
+            Ok(match (op, register_op(op)) {
+                ("noop", _) => Instruction::Noop,
+                ("jmp", _) => Instruction::Jump(arg()?),
+                (_, Some(("add", register))) => Instruction::Add(register, arg()?),
+                (_, Some(("mul", register))) => Instruction::Mul(register, arg()?),
+                (_, Some(("jnz", register))) => Instruction::JumpIfNonZero(register, arg()?),
+                _ => bail!("Invalid instruction: {}", line),
+            })
         })
         .collect()
 }
 
-/// Record the value of X during each cycle and return them in sequence
-fn x_over_time(instructions: &[Option<isize>]) -> Vec<isize> {
-    let mut x = 1;
-    let mut values: Vec<isize> = vec![];
+/// The handheld device's CPU
+struct Cpu {
+    /// Registers `a` through `z`
+    registers: [isize; 26],
+    /// The index of the next instruction to run
+    pc: usize,
+    /// How many cycles have finished
+    cycle: usize,
+}
 
-    for instruction in instructions {
+impl Cpu {
+    /// A CPU as it starts in the puzzle, with `x` set to 1
+    fn new() -> Self {
+        let mut cpu = Cpu {
+            registers: [0; 26],
+            pc: 0,
+            cycle: 0,
+        };
+        *cpu.register_mut('x') = 1;
+        cpu
+    }
+
+    fn register(&self, name: char) -> isize {
+        self.registers[(name as u8 - b'a') as usize]
+    }
+
+    fn register_mut(&mut self, name: char) -> &mut isize {
+        &mut self.registers[(name as u8 - b'a') as usize]
+    }
+
+    /// Apply an instruction's effects once it's finished
+    fn execute(&mut self, instruction: Instruction) {
+        let mut jump = 1;
         match instruction {
-            Some(val) => {
-                values.push(x);
-                values.push(x);
-                x += val;
+            Instruction::Noop => (),
+            Instruction::Add(register, val) => *self.register_mut(register) += val,
+            Instruction::Mul(register, val) => *self.register_mut(register) *= val,
+            Instruction::Jump(offset) => jump = offset,
+            Instruction::JumpIfNonZero(register, offset) => {
+                if self.register(register) != 0 {
+                    jump = offset;
+                }
+            }
+        }
+        // Jumping before the start ends the program, the same as jumping past the end
+        self.pc = self.pc.checked_add_signed(jump).unwrap_or(usize::MAX);
+    }
+
+    /// Run a program until the program counter leaves it, calling `during_cycle` with the cycle
+    /// number (starting from 1) and the CPU's state while each cycle is in progress. Jumps can
+    /// loop forever, so the program is stopped with an error once `max_cycles` have run.
+    fn run(
+        &mut self,
+        program: &[Instruction],
+        max_cycles: usize,
+        mut during_cycle: impl FnMut(usize, &Cpu),
+    ) -> Result<(), Error> {
+        while let Some(&instruction) = program.get(self.pc) {
+            for _ in 0..instruction.cycles() {
+                if self.cycle >= max_cycles {
+                    bail!("Program didn't halt within {} cycles", max_cycles);
+                }
+                self.cycle += 1;
+                during_cycle(self.cycle, self);
             }
-            None => values.push(x),
+            self.execute(instruction);
         }
+        Ok(())
     }
+}
+
+/// Record the value of X during each cycle and return them in sequence
+fn x_over_time(program: &[Instruction], max_cycles: usize) -> Result<Vec<isize>, Error> {
+    let mut values: Vec<isize> = vec![];
+    Cpu::new().run(program, max_cycles, |_, cpu| values.push(cpu.register('x')))?;
+    Ok(values)
+}
 
-    values
+/// How long a program can run before it's assumed to be stuck in a loop
+const MAX_CYCLES: usize = 100_000;
+
+/// Which cycles to sample the signal strength during
+struct Sampling {
+    /// The first cycle to sample
+    start: usize,
+    /// How many cycles apart samples are
+    step: usize,
+    /// How many samples to take
+    count: usize,
+    /// How many cycles the program can run for before giving up on it
+    max_cycles: usize,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling {
+            start: 20,
+            step: 40,
+            count: 6,
+            max_cycles: MAX_CYCLES,
+        }
+    }
+}
+
+fn part1(instructions: &[Instruction], sampling: &Sampling) -> Result<isize, Error> {
+    let values = x_over_time(instructions, sampling.max_cycles)?;
+
+    (0..sampling.count)
+        .map(|i| sampling.start + i * sampling.step)
+        .map(|cycle| {
+            let x = cycle
+                .checked_sub(1)
+                .and_then(|i| values.get(i))
+                .with_context(|| {
+                    format!(
+                        "Can't sample cycle {}, only cycles 1 to {} ran",
+                        cycle,
+                        values.len()
+                    )
+                })?;
+            Ok(x * cycle as isize)
+        })
+        .sum()
+}
+
+/// The shape of the CRT and the sprite it draws
+#[derive(Clone, Copy)]
+struct CrtConfig {
+    width: usize,
+    /// How many rows the CRT should have. Rows aren't wrapped at this height, so a program that
+    /// runs too long or too short only gets a warning.
+    height: usize,
+    /// How many pixels wide the sprite is. Its middle is at X, with any extra pixel to the right.
+    sprite_width: usize,
+    /// How many cycles the program can run for before giving up on it
+    max_cycles: usize,
+}
+
+impl Default for CrtConfig {
+    fn default() -> Self {
+        CrtConfig {
+            width: 40,
+            height: 6,
+            sprite_width: 3,
+            max_cycles: MAX_CYCLES,
+        }
+    }
 }
 
-fn part1(instructions: &[Option<isize>]) -> Result<isize, Error> {
-    let values = x_over_time(instructions);
+/// The pixels a program drew on the CRT
+struct Screen {
+    /// Each row of pixels. The last may be cut short if the program stopped mid-row.
+    rows: Vec<String>,
+    config: CrtConfig,
+}
 
-    if values.len() < 220 {
-        bail!("Insufficient cycles run");
+impl Screen {
+    /// Describe anything about the drawing that doesn't fit the CRT
+    fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if let Some(last) = self.rows.last().filter(|r| r.len() < self.config.width) {
+            problems.push(format!(
+                "The last row only has {} of {} pixels",
+                last.len(),
+                self.config.width
+            ));
+        }
+        if self.rows.len() != self.config.height {
+            problems.push(format!(
+                "{} rows were drawn on a CRT {} rows tall",
+                self.rows.len(),
+                self.config.height
+            ));
+        }
+        problems
     }
+}
 
-    Ok(values[19] * 20
-        + values[59] * 60
-        + values[99] * 100
-        + values[139] * 140
-        + values[179] * 180
-        + values[219] * 220)
+impl Display for Screen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.rows.iter() {
+            writeln!(f, "{}", row)?;
+        }
+        Ok(())
+    }
 }
 
-fn part2(instructions: &[Option<isize>]) -> Result<String, Error> {
-    let values = x_over_time(instructions);
+fn part2(instructions: &[Instruction], config: CrtConfig) -> Result<Screen, Error> {
+    if config.width == 0 {
+        bail!("The CRT needs to be at least one pixel wide");
+    }
+    if config.sprite_width == 0 {
+        bail!("The sprite needs to be at least one pixel wide");
+    }
+
+    let values = x_over_time(instructions, config.max_cycles)?;
+    let left = (config.sprite_width as isize - 1) / 2;
+    let right = config.sprite_width as isize / 2;
 
     let chars: Vec<char> = values
         .into_iter()
         .enumerate()
         .map(|(i, x)| {
-            if x.abs_diff(TryInto::<isize>::try_into(i)? % 40) <= 1 {
+            let column: isize = (i % config.width).try_into()?;
+            if (x - left..=x + right).contains(&column) {
                 Ok('#')
             } else {
                 Ok('.')
@@ -67,18 +268,84 @@ fn part2(instructions: &[Option<isize>]) -> Result<String, Error> {
         })
         .collect::<Result<Vec<char>, Error>>()?;
 
-    let mut output: String = String::new();
+    Ok(Screen {
+        rows: chars
+            .chunks(config.width)
+            .map(|chunk| chunk.iter().collect())
+            .collect(),
+        config,
+    })
+}
 
-    for chunk in chars.chunks_exact(40) {
-        writeln!(output, "{}", chunk.iter().collect::<String>())?;
+/// The Advent of Code font, where each letter is four pixels wide and six tall. Y is really
+/// five pixels wide, so only its first four columns are kept and its fifth lands in the gap.
+const GLYPHS: [(char, [&str; 6]); 18] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Y', ["#...", "#...", ".#.#", "..#.", "..#.", "..#."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+/// Read the letters drawn on the CRT. Letters are spaced five pixels apart.
+fn read_crt(screen: &str) -> Result<String, Error> {
+    let rows: Vec<&str> = screen.lines().collect();
+    if rows.len() != 6 {
+        bail!("Expected 6 rows of pixels, found {}", rows.len());
+    }
+
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut text = String::new();
+    let mut unrecognised = vec![];
+
+    for (i, start) in (0..width).step_by(5).enumerate() {
+        let glyph: Vec<&str> = rows
+            .iter()
+            .map(|row| row.get(start..(start + 4).min(row.len())).unwrap_or(""))
+            .collect();
+        match GLYPHS.iter().find(|(_, pixels)| pixels[..] == glyph[..]) {
+            Some((letter, _)) => text.push(*letter),
+            None => unrecognised.push(i.to_string()),
+        }
     }
-    Ok(output)
+
+    if !unrecognised.is_empty() {
+        bail!(
+            "Unrecognised glyphs at positions {}",
+            unrecognised.join(", ")
+        );
+    }
+
+    Ok(text)
 }
 
 fn main() -> Result<(), Error> {
     let instructions = parse(&read_stdin()?)?;
-    println!("Part 1: {}", part1(&instructions)?);
-    println!("Part 2:\n{}", part2(&instructions)?);
+    println!("Part 1: {}", part1(&instructions, &Sampling::default())?);
+    let screen = part2(&instructions, CrtConfig::default())?;
+    for problem in screen.problems() {
+        eprintln!("Warning: {}", problem);
+    }
+    match read_crt(&screen.to_string()) {
+        Ok(text) => println!("Part 2: {}", text),
+        Err(e) => {
+            println!("Part 2:\n{}", screen);
+            eprintln!("{}", e);
+        }
+    }
     Ok(())
 }
 
@@ -91,13 +358,132 @@ mod tests {
 
     #[test]
     fn part1_example() -> Result<(), Error> {
-        assert_eq!(part1(&parse(SAMPLE)?)?, 13140);
+        assert_eq!(part1(&parse(SAMPLE)?, &Sampling::default())?, 13140);
         Ok(())
     }
 
     #[test]
     fn part2_example() -> Result<(), Error> {
-        assert_eq!(&part2(&parse(SAMPLE)?)?, SAMPLE_RESULT);
+        let screen = part2(&parse(SAMPLE)?, CrtConfig::default())?;
+        assert_eq!(screen.to_string(), SAMPLE_RESULT);
+        assert!(screen.problems().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn extended_instructions() -> Result<(), Error> {
+        let program = parse("addy 3\nmulx 5\naddy -1\njnzy -1\nnoop")?;
+        let mut trace = vec![];
+        let mut cpu = Cpu::new();
+        cpu.run(&program, MAX_CYCLES, |cycle, cpu| {
+            trace.push((cycle, cpu.register('x'), cpu.register('y')))
+        })?;
+        assert_eq!(cpu.register('x'), 5);
+        assert_eq!(cpu.register('y'), 0);
+        assert_eq!(cpu.cycle, 14);
+        assert_eq!(
+            &trace[..5],
+            [(1, 1, 0), (2, 1, 0), (3, 1, 3), (4, 1, 3), (5, 5, 3)]
+        );
+
+        // Programs that never halt are stopped
+        for looping in ["noop\njmp 0", "addx 2\njnzx -1"] {
+            let error = Cpu::new()
+                .run(&parse(looping)?, 1000, |_, _| ())
+                .unwrap_err();
+            assert_eq!(error.to_string(), "Program didn't halt within 1000 cycles");
+        }
+        assert!(part1(&parse("noop\njmp 0")?, &Sampling::default()).is_err());
+        assert!(part2(&parse("noop\njmp 0")?, CrtConfig::default()).is_err());
+
+        assert!(parse("addX 1").is_err());
+        assert!(parse("jmp").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ocr() -> Result<(), Error> {
+        let text = "RGLRBZAU";
+        let screen: String = (0..6)
+            .map(|row| {
+                text.chars()
+                    .map(|c| {
+                        let (_, pixels) = GLYPHS.iter().find(|(l, _)| *l == c).unwrap();
+                        format!("{}.", pixels[row])
+                    })
+                    .collect::<String>()
+                    + "\n"
+            })
+            .collect();
+        assert_eq!(read_crt(&screen)?, text);
+
+        // Drawn by hand, including the fifth column of the Y
+        let drawn = "\
+#..#.#...####..####.###..###...##...##..
+#..#.#...##..#.#....#..#.#..#.#..#.#..#.
+####..#.#.#..#.###..#..#.###..#..#.#....
+#..#...#..###..#....###..#..#.####.#.##.
+#..#...#..#....#....#.#..#..#.#..#.#..#.
+#..#...#..#....####.#..#.###..#..#..###.
+";
+        assert_eq!(read_crt(drawn)?, "HYPERBAG");
+
+        let error = read_crt(SAMPLE_RESULT).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Unrecognised glyphs at positions 0, 1, 2, 3, 4, 5, 6, 7"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn configurable_crt() -> Result<(), Error> {
+        let program = parse(SAMPLE)?;
+        let sampling = Sampling {
+            start: 10,
+            step: 100,
+            count: 3,
+            ..Default::default()
+        };
+        let values = x_over_time(&program, MAX_CYCLES)?;
+        assert_eq!(
+            part1(&program, &sampling)?,
+            values[9] * 10 + values[109] * 110 + values[209] * 210
+        );
+
+        let too_far = Sampling {
+            start: 240,
+            step: 1,
+            count: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            part1(&program, &too_far).unwrap_err().to_string(),
+            "Can't sample cycle 241, only cycles 1 to 240 ran"
+        );
+
+        let screen = part2(
+            &program,
+            CrtConfig {
+                width: 50,
+                height: 5,
+                sprite_width: 1,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(screen.rows.len(), 5);
+        assert_eq!(screen.rows[4].len(), 40);
+        assert_eq!(&screen.rows[0][..10], ".#...#..#.");
+        assert_eq!(
+            screen.problems(),
+            vec!["The last row only has 40 of 50 pixels"]
+        );
+
+        let invisible = CrtConfig {
+            sprite_width: 0,
+            ..Default::default()
+        };
+        assert!(part2(&program, invisible).is_err());
         Ok(())
     }
 }
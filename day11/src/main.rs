@@ -1,220 +1,593 @@
 //! Day 11
 
-use std::str::FromStr;
-
+use std::{
+    collections::HashMap,
+    env,
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, digit1, line_ending, multispace0, one_of, space0},
+    combinator::{all_consuming, map, map_res, value},
+    error::VerboseError,
+    multi::{fold_many0, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    Finish, IResult,
+};
+use num::{integer::lcm, BigUint, Zero};
 use util::*;
 
-#[derive(Clone)]
-enum Operation {
-    Add(usize),
-    Mul(usize),
-    AddSelf,
-    MulSelf,
+/// An arithmetic expression for a monkey's new worry level
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Old,
+    Const(usize),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
 }
 
-// PART 1
+impl Expr {
+    /// Evaluate the expression for an old worry level, keeping every intermediate value below
+    /// `modulus` if one is given.
+    ///
+    /// Worry levels can't go below zero, so a subtraction that would is an error. Under a
+    /// modulus the true value is lost, so subtraction wraps around instead, which matches the
+    /// true value whenever that isn't below zero.
+    fn eval<W: Worry>(&self, old: &W, modulus: Option<usize>) -> Result<W, Error> {
+        let value = match self {
+            Expr::Old => old.clone(),
+            Expr::Const(n) => W::from(*n),
+            Expr::Add(lhs, rhs) => lhs.eval(old, modulus)?.plus(&rhs.eval(old, modulus)?),
+            Expr::Mul(lhs, rhs) => lhs.eval(old, modulus)?.times(&rhs.eval(old, modulus)?),
+            Expr::Sub(lhs, rhs) => {
+                let lhs = lhs.eval(old, modulus)?;
+                let rhs = rhs.eval(old, modulus)?;
+                match modulus {
+                    Some(m) => lhs.plus(&W::from(m)).minus(&rhs.relieve(Relief::Modulo(m))),
+                    None => lhs.minus(&rhs),
+                }
+                .with_context(|| format!("Worry level went below zero in {}", self))?
+            }
+        };
 
-#[derive(Clone)]
-struct Monkey {
+        Ok(match modulus {
+            Some(m) => value.relieve(Relief::Modulo(m)),
+            None => value,
+        })
+    }
+}
+
+fn parse_usize(input: &str) -> IResult<&str, usize, VerboseError<&str>> {
+    map_res(digit1, |num: &str| num.parse::<usize>())(input)
+}
+
+/// Parse `old`, a number, or a parenthesized expression
+fn parse_factor(input: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    alt((
+        value(Expr::Old, tag("old")),
+        map(parse_usize, Expr::Const),
+        delimited(
+            pair(char('('), space0),
+            parse_operation,
+            pair(space0, char(')')),
+        ),
+    ))(input)
+}
+
+/// Parse a product of factors
+fn parse_term(input: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (input, first) = parse_factor(input)?;
+    fold_many0(
+        preceded(delimited(space0, char('*'), space0), parse_factor),
+        move || first.clone(),
+        |lhs, rhs| Expr::Mul(Box::new(lhs), Box::new(rhs)),
+    )(input)
+}
+
+/// Parse a sum or difference of terms, where * binds tighter than + and -
+fn parse_operation(input: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (input, first) = parse_term(input)?;
+    fold_many0(
+        pair(delimited(space0, one_of("+-"), space0), parse_term),
+        move || first.clone(),
+        |lhs, (op, rhs)| {
+            if op == '+' {
+                Expr::Add(Box::new(lhs), Box::new(rhs))
+            } else {
+                Expr::Sub(Box::new(lhs), Box::new(rhs))
+            }
+        },
+    )(input)
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Old => write!(f, "old"),
+            Expr::Const(n) => write!(f, "{}", n),
+            Expr::Add(lhs, rhs) => write!(f, "{} + {}", lhs, rhs),
+            // Subtraction isn't associative, so sums on the right need to stay grouped
+            Expr::Sub(lhs, rhs) => match **rhs {
+                Expr::Add(..) | Expr::Sub(..) => write!(f, "{} - ({})", lhs, rhs),
+                _ => write!(f, "{} - {}", lhs, rhs),
+            },
+            Expr::Mul(lhs, rhs) => {
+                for (i, side) in [lhs, rhs].into_iter().enumerate() {
+                    if i == 1 {
+                        write!(f, " * ")?;
+                    }
+                    match **side {
+                        Expr::Add(..) | Expr::Sub(..) => write!(f, "({})", side)?,
+                        _ => write!(f, "{}", side)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        all_consuming(delimited(space0, parse_operation, space0))(s)
+            .finish()
+            .map(|(_, expr)| expr)
+            .map_err(|e| anyhow!("Invalid operation: {}", e))
+    }
+}
+
+/// A monkey playing keep away, holding items of type `I`
+#[derive(Clone, Debug)]
+struct Monkey<I = usize> {
     inspections: usize,
-    items: Vec<usize>,
-    operation: Operation,
+    items: Vec<I>,
+    operation: Expr,
     test_mod: usize,
     test_true: usize,
     test_false: usize,
 }
 
-impl FromStr for Monkey {
-    type Err = Error;
-
-    fn from_str(input: &str) -> Result<Self, Self::Err> {
-        let mut lines = input.lines();
-        lines.next(); // Skip monkey number
-
-        // Items
-        let items: Vec<usize> = lines
-            .next()
-            .context("Not enough lines")?
-            .split_once(':')
-            .context("No colon on item line")?
-            .1
-            .split(',')
-            .map(|item| item.trim().parse().map_err(Error::from))
-            .collect::<Result<Vec<usize>, Error>>()?;
-
-        let mut op_tokens = lines
-            .next()
-            .context("Not enough lines")?
-            .split_once('=')
-            .context("No = on op line")?
-            .1
-            .trim()
-            .split(' ');
-
-        let lhs = op_tokens.next().context("No lhs")?;
-        let operator = op_tokens.next().context("No operator")?;
-        let rhs = op_tokens.next().context("No rhs")?;
-
-        let operation = match (lhs, operator, rhs) {
-            ("old", "+", "old") => Operation::AddSelf,
-            ("old", "*", "old") => Operation::MulSelf,
-            ("old", "+", num) => Operation::Add(num.parse()?),
-            ("old", "*", num) => Operation::Mul(num.parse()?),
-            _ => bail!("Invalid operation"),
-        };
+/// Parse a monkey's number along with its specification
+fn parse_monkey(input: &str) -> IResult<&str, (usize, Monkey), VerboseError<&str>> {
+    map(
+        tuple((
+            delimited(tag("Monkey "), parse_usize, pair(char(':'), line_ending)),
+            delimited(
+                pair(space0, tag("Starting items:")),
+                separated_list0(char(','), preceded(space0, parse_usize)),
+                line_ending,
+            ),
+            delimited(
+                pair(space0, tag("Operation: new =")),
+                delimited(space0, parse_operation, space0),
+                line_ending,
+            ),
+            delimited(
+                pair(space0, tag("Test: divisible by ")),
+                parse_usize,
+                line_ending,
+            ),
+            delimited(
+                pair(space0, tag("If true: throw to monkey ")),
+                parse_usize,
+                line_ending,
+            ),
+            preceded(pair(space0, tag("If false: throw to monkey ")), parse_usize),
+        )),
+        |(id, items, operation, test_mod, test_true, test_false)| {
+            (
+                id,
+                Monkey {
+                    inspections: 0,
+                    items,
+                    operation,
+                    test_mod,
+                    test_true,
+                    test_false,
+                },
+            )
+        },
+    )(input)
+}
 
-        let test_mod: usize = lines
-            .next()
-            .context("Not enough lines")?
-            .rsplit_once(' ')
-            .context("No space on test mod line")?
-            .1
-            .parse()?;
-
-        let test_true = lines
-            .next()
-            .context("Not enough lines")?
-            .rsplit_once(' ')
-            .context("No space on test true line")?
-            .1
-            .parse()?;
-
-        let test_false = lines
-            .next()
-            .context("Not enough lines")?
-            .rsplit_once(' ')
-            .context("No space on test false line")?
-            .1
-            .parse()?;
-
-        Ok(Monkey {
-            inspections: 0,
-            items,
-            operation,
-            test_mod,
-            test_true,
-            test_false,
+/// Parse every monkey, making sure they're numbered in order and only throw to each other
+fn parse(input: &str) -> Result<Vec<Monkey>, Error> {
+    let (_, numbered) = all_consuming(terminated(
+        separated_list1(pair(line_ending, line_ending), parse_monkey),
+        multispace0,
+    ))(input)
+    .finish()
+    .map_err(|e| anyhow!("{}", e))?;
+
+    let count = numbered.len();
+    numbered
+        .into_iter()
+        .enumerate()
+        .map(|(i, (id, monkey))| {
+            if id != i {
+                bail!("Expected monkey {} but found monkey {}", i, id);
+            }
+            if monkey.test_mod == 0 {
+                bail!("Monkey {} tests divisibility by zero", id);
+            }
+            for target in [monkey.test_true, monkey.test_false] {
+                if target >= count {
+                    bail!(
+                        "Monkey {} throws to monkey {}, but there are only {} monkeys",
+                        id,
+                        target,
+                        count
+                    );
+                }
+            }
+            Ok(monkey)
         })
+        .collect()
+}
+
+/// A worry level that monkeys can operate on
+trait Worry: Clone + From<usize> {
+    fn plus(&self, rhs: &Self) -> Self;
+    /// Subtract, or `None` if the result would be below zero
+    fn minus(&self, rhs: &Self) -> Option<Self>;
+    fn times(&self, rhs: &Self) -> Self;
+    /// Make the worry level more manageable after an inspection
+    fn relieve(self, relief: Relief) -> Self;
+    /// Check the worry level against a monkey's test
+    fn divisible_by(&self, test_mod: usize) -> bool;
+}
+
+/// How worry levels are kept manageable after each inspection
+#[derive(Clone, Copy)]
+enum Relief {
+    /// Worry is divided by the given amount, as in part 1
+    Divide(usize),
+    /// Worry is reduced modulo the given amount, as in part 2. This must be a multiple of every
+    /// monkey's test value, such as their LCM.
+    Modulo(usize),
+    /// Worry is left to grow, which needs a worry type that can't overflow
+    None,
+}
+
+impl Worry for usize {
+    fn plus(&self, rhs: &Self) -> Self {
+        self + rhs
+    }
+
+    fn minus(&self, rhs: &Self) -> Option<Self> {
+        self.checked_sub(*rhs)
+    }
+
+    fn times(&self, rhs: &Self) -> Self {
+        self * rhs
+    }
+
+    fn relieve(self, relief: Relief) -> Self {
+        match relief {
+            Relief::Divide(divisor) => self / divisor,
+            Relief::Modulo(modulus) => self % modulus,
+            Relief::None => self,
+        }
+    }
+
+    fn divisible_by(&self, test_mod: usize) -> bool {
+        self.is_multiple_of(test_mod)
     }
 }
 
-fn parse(input: &str) -> Result<Vec<Monkey>, Error> {
-    input.split("\n\n").map(|block| block.parse()).collect()
-}
-
-/// Run a round as indicated in part 1
-fn turn(monkeys: &mut [Monkey]) -> Result<(), Error> {
-    // Using index instead of iterator to avoid taking an exclusive borrow on monkeys
-    for i in 0..monkeys.len() {
-        // (item, target)
-        let throws: Vec<(usize, usize)> = {
-            let monkey = &monkeys[i];
-            monkey
-                .items
-                .iter()
-                .map(|item| {
-                    let worry = match monkey.operation {
-                        Operation::AddSelf => item + item,
-                        Operation::MulSelf => item * item,
-                        Operation::Add(val) => item + val,
-                        Operation::Mul(val) => item * val,
-                    } / 3;
-
-                    if worry % monkey.test_mod == 0 {
-                        (worry, monkey.test_true)
-                    } else {
-                        (worry, monkey.test_false)
-                    }
-                })
-                .collect()
-        };
+impl Worry for BigUint {
+    fn plus(&self, rhs: &Self) -> Self {
+        self + rhs
+    }
+
+    fn minus(&self, rhs: &Self) -> Option<Self> {
+        (self >= rhs).then(|| self - rhs)
+    }
 
-        let monkey = monkeys.get_mut(i).unwrap();
-        monkey.items.clear();
-        monkey.inspections += throws.len();
+    fn times(&self, rhs: &Self) -> Self {
+        self * rhs
+    }
 
-        for (item, target) in throws {
-            monkeys
-                .get_mut(target)
-                .with_context(|| format!("Invalid target {}", target))?
-                .items
-                .push(item);
+    fn relieve(self, relief: Relief) -> Self {
+        match relief {
+            Relief::Divide(divisor) => self / divisor,
+            Relief::Modulo(modulus) => self % modulus,
+            Relief::None => self,
         }
     }
 
-    Ok(())
+    fn divisible_by(&self, test_mod: usize) -> bool {
+        (self % test_mod).is_zero()
+    }
 }
 
-fn part1(mut monkeys: Vec<Monkey>) -> Result<usize, Error> {
-    for _ in 0..20 {
-        turn(&mut monkeys)?;
+/// An item being thrown around, which keeps its identity as its worry level changes
+#[derive(Clone)]
+struct Item<W> {
+    /// The item's position in the input, counting from the first monkey's first item
+    id: usize,
+    worry: W,
+}
+
+/// Everything that happened while a monkey inspected an item
+struct Inspection<'a, W> {
+    monkey: usize,
+    item: usize,
+    old: &'a W,
+    operation: &'a Expr,
+    /// Worry after the operation
+    operated: &'a W,
+    relief: Relief,
+    /// Worry after relief
+    relieved: &'a W,
+    test_mod: usize,
+    divisible: bool,
+    target: usize,
+}
+
+/// Watches a keep away game as it's played
+trait Observer<W> {
+    /// A monkey starts its turn
+    fn turn(&mut self, _round: usize, _monkey: usize) {}
+    /// A monkey inspected and threw an item
+    fn inspect(&mut self, _round: usize, _inspection: &Inspection<W>) {}
+    /// Every monkey has had its turn
+    fn round_end(&mut self, _round: usize, _monkeys: &[Monkey<Item<W>>]) {}
+}
+
+impl<W> Observer<W> for () {}
+
+impl<W, O: Observer<W>> Observer<W> for Option<O> {
+    fn turn(&mut self, round: usize, monkey: usize) {
+        if let Some(observer) = self {
+            observer.turn(round, monkey);
+        }
     }
 
-    let mut inspections: Vec<usize> = monkeys.iter().map(|m| m.inspections).collect();
+    fn inspect(&mut self, round: usize, inspection: &Inspection<W>) {
+        if let Some(observer) = self {
+            observer.inspect(round, inspection);
+        }
+    }
 
-    inspections.sort();
-    inspections.reverse();
+    fn round_end(&mut self, round: usize, monkeys: &[Monkey<Item<W>>]) {
+        if let Some(observer) = self {
+            observer.round_end(round, monkeys);
+        }
+    }
+}
 
-    Ok(inspections.first().context("No first value")?
-        * inspections.get(1).context("No second value")?)
+impl<W, A: Observer<W>, B: Observer<W>> Observer<W> for (A, B) {
+    fn turn(&mut self, round: usize, monkey: usize) {
+        self.0.turn(round, monkey);
+        self.1.turn(round, monkey);
+    }
+
+    fn inspect(&mut self, round: usize, inspection: &Inspection<W>) {
+        self.0.inspect(round, inspection);
+        self.1.inspect(round, inspection);
+    }
+
+    fn round_end(&mut self, round: usize, monkeys: &[Monkey<Item<W>>]) {
+        self.0.round_end(round, monkeys);
+        self.1.round_end(round, monkeys);
+    }
 }
 
-// PART 2
+/// Describes the first few rounds in the style of the puzzle
+struct Narrator {
+    rounds: usize,
+    lines: Vec<String>,
+}
 
-/// A monkey where each of its held items keeps track of its value modulo the test value of each
-/// monkey.
-struct ModularMonkey {
-    inspections: usize,
-    items: Vec<Vec<usize>>,
-    operation: Operation,
-    test_index: usize,
-    test_true: usize,
-    test_false: usize,
+impl<W: Display> Observer<W> for Narrator {
+    fn turn(&mut self, round: usize, monkey: usize) {
+        if round <= self.rounds {
+            self.lines.push(format!("Monkey {}:", monkey));
+        }
+    }
+
+    fn inspect(&mut self, round: usize, i: &Inspection<W>) {
+        if round > self.rounds {
+            return;
+        }
+
+        self.lines.push(format!(
+            "  Monkey inspects an item with a worry level of {}.",
+            i.old
+        ));
+        self.lines.push(match i.operation {
+            Expr::Add(lhs, rhs) if **lhs == Expr::Old && **rhs == Expr::Old => {
+                format!("    Worry level increases by itself to {}.", i.operated)
+            }
+            Expr::Mul(lhs, rhs) if **lhs == Expr::Old && **rhs == Expr::Old => {
+                format!("    Worry level is multiplied by itself to {}.", i.operated)
+            }
+            Expr::Add(lhs, rhs) if **lhs == Expr::Old => {
+                format!("    Worry level increases by {} to {}.", rhs, i.operated)
+            }
+            Expr::Mul(lhs, rhs) if **lhs == Expr::Old => {
+                format!(
+                    "    Worry level is multiplied by {} to {}.",
+                    rhs, i.operated
+                )
+            }
+            operation => format!("    Worry level becomes {} = {}.", operation, i.operated),
+        });
+        match i.relief {
+            Relief::Divide(divisor) => self.lines.push(format!(
+                "    Monkey gets bored with item. Worry level is divided by {} to {}.",
+                divisor, i.relieved
+            )),
+            Relief::Modulo(modulus) => self.lines.push(format!(
+                "    Monkey gets bored with item. Worry level is reduced modulo {} to {}.",
+                modulus, i.relieved
+            )),
+            Relief::None => (),
+        }
+        self.lines.push(format!(
+            "    Current worry level is {}divisible by {}.",
+            if i.divisible { "" } else { "not " },
+            i.test_mod
+        ));
+        self.lines.push(format!(
+            "    Item with worry level {} is thrown to monkey {}.",
+            i.relieved, i.target
+        ));
+    }
 }
 
-/// A keep away game, tracking the monkeys involved as well as a list of the modulo each monkey
-/// checks
-struct ModularKeepAway {
-    mods: Vec<usize>,
-    monkeys: Vec<ModularMonkey>,
+/// Lists how many items each monkey has inspected after certain rounds
+struct RoundTable {
+    rounds: Vec<usize>,
+    lines: Vec<String>,
 }
 
-impl ModularKeepAway {
-    /// Run a round as indicated in part 2
-    fn round(&mut self) -> Result<(), Error> {
-        // Using index instead of iterator to avoid taking an exclusive borrow on monkeys
-        for i in 0..self.monkeys.len() {
-            // (item, target)
-            let throws: Vec<(Vec<usize>, usize)> = {
-                let monkey = &self.monkeys[i];
-                monkey
+impl<W> Observer<W> for RoundTable {
+    fn round_end(&mut self, round: usize, monkeys: &[Monkey<Item<W>>]) {
+        if !self.rounds.contains(&round) {
+            return;
+        }
+
+        if !self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        self.lines.push(format!("== After round {} ==", round));
+        for (i, monkey) in monkeys.iter().enumerate() {
+            self.lines.push(format!(
+                "Monkey {} inspected items {} times.",
+                i, monkey.inspections
+            ));
+        }
+    }
+}
+
+/// Follows a single item from monkey to monkey
+struct Journey<W> {
+    item: usize,
+    /// (round, the monkey that threw it, its worry when thrown, the monkey it was thrown to)
+    throws: Vec<(usize, usize, W, usize)>,
+}
+
+impl<W: Clone> Observer<W> for Journey<W> {
+    fn inspect(&mut self, round: usize, inspection: &Inspection<W>) {
+        if inspection.item == self.item {
+            self.throws.push((
+                round,
+                inspection.monkey,
+                inspection.relieved.clone(),
+                inspection.target,
+            ));
+        }
+    }
+}
+
+impl<W: Display> Display for Journey<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Item {}:", self.item)?;
+        for (round, from, worry, to) in self.throws.iter() {
+            write!(
+                f,
+                "\n  Round {}: monkey {} throws it to monkey {} with a worry level of {}",
+                round, from, to, worry
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A keep away game between monkeys, with a policy for relieving worry after each inspection
+struct KeepAway<W = usize> {
+    monkeys: Vec<Monkey<Item<W>>>,
+    relief: Relief,
+    /// How many rounds have been played so far
+    rounds: usize,
+}
+
+impl<W: Worry> KeepAway<W> {
+    fn new(monkeys: Vec<Monkey>, relief: Relief) -> Self {
+        let mut next_id = 0..;
+        let monkeys = monkeys
+            .into_iter()
+            .map(|monkey| Monkey {
+                inspections: monkey.inspections,
+                items: monkey
                     .items
-                    .iter()
-                    .map(|item| {
-                        let worry: Vec<usize> = item
-                            .iter()
-                            .zip(self.mods.iter())
-                            .map(|(item_mod, m)| {
-                                (match monkey.operation {
-                                    Operation::AddSelf => item_mod + item_mod,
-                                    Operation::MulSelf => item_mod * item_mod,
-                                    Operation::Add(val) => item_mod + val,
-                                    Operation::Mul(val) => item_mod * val,
-                                }) % m
-                            })
-                            .collect();
-                        if worry[monkey.test_index] == 0 {
-                            (worry, monkey.test_true)
-                        } else {
-                            (worry, monkey.test_false)
-                        }
+                    .into_iter()
+                    .zip(&mut next_id)
+                    .map(|(worry, id)| Item {
+                        id,
+                        worry: W::from(worry),
                     })
-                    .collect()
-            };
+                    .collect(),
+                operation: monkey.operation,
+                test_mod: monkey.test_mod,
+                test_true: monkey.test_true,
+                test_false: monkey.test_false,
+            })
+            .collect();
+
+        KeepAway {
+            monkeys,
+            relief,
+            rounds: 0,
+        }
+    }
 
-            let monkey = self.monkeys.get_mut(i).unwrap();
-            monkey.items.clear();
-            monkey.inspections += throws.len();
+    /// Run a single round, where every monkey takes a turn
+    fn round(&mut self, observer: &mut impl Observer<W>) -> Result<(), Error> {
+        let modulus = match self.relief {
+            Relief::Modulo(m) => Some(m),
+            _ => None,
+        };
+        self.rounds += 1;
+
+        // Using index instead of iterator to avoid taking an exclusive borrow on monkeys
+        for i in 0..self.monkeys.len() {
+            observer.turn(self.rounds, i);
+
+            let monkey = &mut self.monkeys[i];
+            let items = std::mem::take(&mut monkey.items);
+            monkey.inspections += items.len();
+
+            // (item, target)
+            let throws: Vec<(Item<W>, usize)> = items
+                .into_iter()
+                .map(|item| {
+                    let operated = monkey.operation.eval(&item.worry, modulus)?;
+                    let worry = operated.clone().relieve(self.relief);
+                    let divisible = worry.divisible_by(monkey.test_mod);
+                    let target = if divisible {
+                        monkey.test_true
+                    } else {
+                        monkey.test_false
+                    };
+
+                    observer.inspect(
+                        self.rounds,
+                        &Inspection {
+                            monkey: i,
+                            item: item.id,
+                            old: &item.worry,
+                            operation: &monkey.operation,
+                            operated: &operated,
+                            relief: self.relief,
+                            relieved: &worry,
+                            test_mod: monkey.test_mod,
+                            divisible,
+                            target,
+                        },
+                    );
+
+                    Ok((Item { id: item.id, worry }, target))
+                })
+                .collect::<Result<_, Error>>()?;
 
             for (item, target) in throws {
                 self.monkeys
@@ -225,46 +598,108 @@ impl ModularKeepAway {
             }
         }
 
+        observer.round_end(self.rounds, &self.monkeys);
+
         Ok(())
     }
+
+    /// Multiply the inspection counts of the two busiest monkeys
+    fn monkey_business(&self) -> Result<usize, Error> {
+        monkey_business(self.monkeys.iter().map(|m| m.inspections).collect())
+    }
 }
 
-/// Convert a part 1 monkey list to a part 2 keep away game
-fn monkeys_to_modulars(monkeys: Vec<Monkey>) -> ModularKeepAway {
-    let mods: Vec<usize> = monkeys.iter().map(|m| m.test_mod).collect();
+impl KeepAway {
+    /// Follow a single item through one round, returning where it ends up and which monkeys
+    /// inspected it along the way
+    fn item_round(
+        &self,
+        mut worry: usize,
+        mut position: usize,
+        modulus: usize,
+    ) -> Result<(usize, usize, Vec<usize>), Error> {
+        let mut inspectors = vec![];
+
+        loop {
+            let monkey = self
+                .monkeys
+                .get(position)
+                .with_context(|| format!("Invalid target {}", position))?;
+            inspectors.push(position);
+
+            worry = monkey.operation.eval(&worry, Some(modulus))?;
+            let target = if worry.divisible_by(monkey.test_mod) {
+                monkey.test_true
+            } else {
+                monkey.test_false
+            };
 
-    let mod_monkeys: Vec<ModularMonkey> = monkeys
-        .into_iter()
-        .enumerate()
-        .map(|(i, monkey)| ModularMonkey {
-            inspections: monkey.inspections,
-            items: monkey
-                .items
-                .iter()
-                .map(|item| mods.iter().map(|m| item % m).collect())
-                .collect(),
-            operation: monkey.operation,
-            test_index: i,
-            test_true: monkey.test_true,
-            test_false: monkey.test_false,
-        })
-        .collect();
+            // Monkeys later in the order get to inspect the item again this round
+            let passed = target <= position;
+            position = target;
+            if passed {
+                break;
+            }
+        }
 
-    ModularKeepAway {
-        mods,
-        monkeys: mod_monkeys,
+        Ok((worry, position, inspectors))
     }
-}
 
-fn part2(monkeys: Vec<Monkey>) -> Result<usize, Error> {
-    let mut mod_monkeys = monkeys_to_modulars(monkeys);
+    /// Count each monkey's inspections after some number of rounds without playing them all.
+    ///
+    /// Items never affect each other, and under modulo relief each item only has finitely many
+    /// states, so each item's journey eventually repeats and can be extrapolated.
+    fn inspections_after(&self, rounds: usize) -> Result<Vec<usize>, Error> {
+        let Relief::Modulo(modulus) = self.relief else {
+            bail!("Cycle detection needs modulo relief");
+        };
 
-    for _ in 0..10000 {
-        mod_monkeys.round()?;
-    }
+        let mut inspections: Vec<usize> = self.monkeys.iter().map(|m| m.inspections).collect();
+
+        for (start, monkey) in self.monkeys.iter().enumerate() {
+            for Item { worry: item, .. } in monkey.items.iter() {
+                // (worry, position) -> the first round the item was in that state
+                let mut seen: HashMap<(usize, usize), usize> = HashMap::new();
+                // The monkeys that inspected the item in each round
+                let mut trail: Vec<Vec<usize>> = vec![];
+                let mut state = (item % modulus, start);
+
+                while trail.len() < rounds {
+                    if let Some(&cycle_start) = seen.get(&state) {
+                        let cycle = &trail[cycle_start..];
+                        let remaining = rounds - trail.len();
+                        let repeats = remaining / cycle.len();
+                        for inspectors in cycle.iter() {
+                            for &i in inspectors.iter() {
+                                inspections[i] += repeats;
+                            }
+                        }
+                        for inspectors in cycle[..remaining % cycle.len()].iter() {
+                            for &i in inspectors.iter() {
+                                inspections[i] += 1;
+                            }
+                        }
+                        break;
+                    }
 
-    let mut inspections: Vec<usize> = mod_monkeys.monkeys.iter().map(|m| m.inspections).collect();
+                    seen.insert(state, trail.len());
+                    let (worry, position, inspectors) =
+                        self.item_round(state.0, state.1, modulus)?;
+                    for &i in inspectors.iter() {
+                        inspections[i] += 1;
+                    }
+                    trail.push(inspectors);
+                    state = (worry, position);
+                }
+            }
+        }
+
+        Ok(inspections)
+    }
+}
 
+/// Multiply the inspection counts of the two busiest monkeys
+fn monkey_business(mut inspections: Vec<usize>) -> Result<usize, Error> {
     inspections.sort();
     inspections.reverse();
 
@@ -272,10 +707,84 @@ fn part2(monkeys: Vec<Monkey>) -> Result<usize, Error> {
         * inspections.get(1).context("No second value")?)
 }
 
+/// Relief that keeps worry levels small without changing any monkey's test results
+fn lcm_relief(monkeys: &[Monkey]) -> Relief {
+    Relief::Modulo(monkeys.iter().fold(1, |acc, m| lcm(acc, m.test_mod)))
+}
+
+/// Play keep away for some number of rounds and find the monkey business
+fn play<W: Worry>(
+    monkeys: Vec<Monkey>,
+    relief: Relief,
+    rounds: usize,
+    observer: &mut impl Observer<W>,
+) -> Result<usize, Error> {
+    let mut game: KeepAway<W> = KeepAway::new(monkeys, relief);
+
+    for _ in 0..rounds {
+        game.round(observer)?;
+    }
+
+    game.monkey_business()
+}
+
+fn part1(monkeys: Vec<Monkey>, observer: &mut impl Observer<usize>) -> Result<usize, Error> {
+    play(monkeys, Relief::Divide(3), 20, observer)
+}
+
+fn part2(monkeys: Vec<Monkey>) -> Result<usize, Error> {
+    let relief = lcm_relief(&monkeys);
+    monkey_business(KeepAway::new(monkeys, relief).inspections_after(10000)?)
+}
+
 fn main() -> Result<(), Error> {
     let monkeys = parse(&read_stdin()?)?;
-    println!("Part 1: {}", part1(monkeys.clone())?);
-    println!("Part 2: {}", part2(monkeys)?);
+
+    // Optional debugging output for part 1
+    let flag = |name: &str| -> Result<Option<usize>, Error> {
+        let mut args = env::args().skip_while(|arg| arg != name);
+        match args.nth(1) {
+            Some(value) => Ok(Some(value.parse()?)),
+            None => Ok(None),
+        }
+    };
+    let narrator = flag("--narrate")?.map(|rounds| Narrator {
+        rounds,
+        lines: vec![],
+    });
+    let table = env::args().any(|arg| arg == "--table").then(|| RoundTable {
+        rounds: (1..=20).collect(),
+        lines: vec![],
+    });
+    let journey = flag("--item")?.map(|item| Journey {
+        item,
+        throws: vec![],
+    });
+    let mut observer = (narrator, (table, journey));
+
+    println!("Part 1: {}", part1(monkeys.clone(), &mut observer)?);
+    println!("Part 2: {}", part2(monkeys.clone())?);
+
+    // Play without any relief, letting worry grow as large as it needs to
+    if let Some(rounds) = flag("--no-relief")? {
+        println!(
+            "No relief after {} rounds: {}",
+            rounds,
+            play::<BigUint>(monkeys, Relief::None, rounds, &mut ())?
+        );
+    }
+
+    let (narrator, (table, journey)) = observer;
+    for lines in [narrator.map(|n| n.lines), table.map(|t| t.lines)]
+        .into_iter()
+        .flatten()
+    {
+        println!("\n{}", lines.join("\n"));
+    }
+    if let Some(journey) = journey {
+        println!("\n{}", journey);
+    }
+
     Ok(())
 }
 
@@ -287,7 +796,7 @@ mod tests {
 
     #[test]
     fn part1_example() -> Result<(), Error> {
-        assert_eq!(part1(parse(SAMPLE)?)?, 10605);
+        assert_eq!(part1(parse(SAMPLE)?, &mut ())?, 10605);
         Ok(())
     }
 
@@ -296,4 +805,144 @@ mod tests {
         assert_eq!(part2(parse(SAMPLE)?)?, 2713310158);
         Ok(())
     }
+
+    #[test]
+    fn unbounded_worry() -> Result<(), Error> {
+        let monkeys = parse(SAMPLE)?;
+        let relief = lcm_relief(&monkeys);
+        assert_eq!(
+            play::<BigUint>(monkeys.clone(), Relief::None, 20, &mut ())?,
+            play::<usize>(monkeys, relief, 20, &mut ())?
+        );
+
+        // Subtraction behaves the same for every worry type and under modulo relief
+        let subtracting = parse(&SAMPLE.replacen("new = old + 6", "new = old * 2 - 1", 1))?;
+        assert_eq!(
+            play::<BigUint>(subtracting.clone(), Relief::Divide(3), 4, &mut ())?,
+            play::<usize>(subtracting.clone(), Relief::Divide(3), 4, &mut ())?
+        );
+        let relief = lcm_relief(&subtracting);
+        let unbounded = play::<BigUint>(subtracting.clone(), Relief::None, 20, &mut ())?;
+        assert_eq!(
+            play::<usize>(subtracting.clone(), relief, 20, &mut ())?,
+            unbounded
+        );
+        assert_eq!(
+            monkey_business(KeepAway::new(subtracting.clone(), relief).inspections_after(20)?)?,
+            unbounded
+        );
+        assert!(part2(subtracting).is_ok());
+
+        let negative = parse(&SAMPLE.replacen("new = old + 6", "new = old - 60", 1))?;
+        assert!(play::<BigUint>(negative, Relief::None, 1, &mut ()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn operations() -> Result<(), Error> {
+        let expr: Expr = "(old - 3) * 2 + old * old".parse()?;
+        assert_eq!(expr.eval(&10, None)?, 114);
+        assert_eq!(expr.eval(&10, Some(7))?, 114 % 7);
+        assert_eq!(
+            "2 * (3 - old)"
+                .parse::<Expr>()?
+                .eval(&5, None)
+                .unwrap_err()
+                .to_string(),
+            "Worry level went below zero in 3 - old"
+        );
+        // Modular subtraction wraps around where plain subtraction would stop
+        assert_eq!("2 * (3 - old)".parse::<Expr>()?.eval(&5, Some(7))?, 3);
+        assert_eq!(
+            "old * 19".parse::<Expr>()?,
+            Expr::Mul(Box::new(Expr::Old), Box::new(Expr::Const(19)))
+        );
+        assert!("old * / 2".parse::<Expr>().is_err());
+        assert!("(old + 2".parse::<Expr>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cycle_detection() -> Result<(), Error> {
+        let monkeys = parse(SAMPLE)?;
+        let relief = lcm_relief(&monkeys);
+        let game = KeepAway::new(monkeys.clone(), relief);
+        for rounds in [0, 1, 20, 1000, 3517] {
+            assert_eq!(
+                monkey_business(game.inspections_after(rounds)?)?,
+                play::<usize>(monkeys.clone(), relief, rounds, &mut ())?
+            );
+        }
+        assert!(game.inspections_after(1_000_000_000_000).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn observers() -> Result<(), Error> {
+        let monkeys = parse(SAMPLE)?;
+        let mut observer = (
+            Narrator {
+                rounds: 1,
+                lines: vec![],
+            },
+            (
+                RoundTable {
+                    rounds: vec![20],
+                    lines: vec![],
+                },
+                Journey {
+                    item: 0,
+                    throws: vec![],
+                },
+            ),
+        );
+        part1(monkeys.clone(), &mut observer)?;
+        let (narrator, (table, journey)) = observer;
+
+        assert_eq!(
+            narrator.lines[..7].join("\n"),
+            "Monkey 0:\n  \
+             Monkey inspects an item with a worry level of 79.\n    \
+             Worry level is multiplied by 19 to 1501.\n    \
+             Monkey gets bored with item. Worry level is divided by 3 to 500.\n    \
+             Current worry level is not divisible by 23.\n    \
+             Item with worry level 500 is thrown to monkey 3.\n  \
+             Monkey inspects an item with a worry level of 98."
+        );
+        assert!(narrator
+            .lines
+            .contains(&"    Worry level is multiplied by itself to 6241.".to_owned()));
+        assert_eq!(
+            table.lines.join("\n"),
+            "== After round 20 ==\n\
+             Monkey 0 inspected items 101 times.\n\
+             Monkey 1 inspected items 95 times.\n\
+             Monkey 2 inspected items 7 times.\n\
+             Monkey 3 inspected items 105 times."
+        );
+        assert_eq!(journey.throws[..2], [(1, 0, 500, 3), (1, 3, 167, 1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn validation() {
+        let renumbered = SAMPLE.replacen("Monkey 1:", "Monkey 2:", 1);
+        assert_eq!(
+            parse(&renumbered).unwrap_err().to_string(),
+            "Expected monkey 1 but found monkey 2"
+        );
+
+        let bad_target = SAMPLE.replacen("throw to monkey 3", "throw to monkey 4", 1);
+        assert_eq!(
+            parse(&bad_target).unwrap_err().to_string(),
+            "Monkey 0 throws to monkey 4, but there are only 4 monkeys"
+        );
+
+        let swapped = SAMPLE.replacen(
+            "    If true: throw to monkey 2\n    If false: throw to monkey 3",
+            "    If false: throw to monkey 3\n    If true: throw to monkey 2",
+            1,
+        );
+        assert!(parse(&swapped).is_err());
+    }
 }
@@ -0,0 +1,437 @@
+//! Packets from the distress signal in day 13, which are lists of integers and other lists.
+
+use std::{cmp::Ordering, fmt, io::BufRead, slice, str::FromStr};
+
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{digit1, space0},
+    combinator::{all_consuming, map, map_res},
+    error::VerboseError,
+    multi::separated_list0,
+    sequence::{delimited, pair},
+    Finish, IResult,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use util::*;
+
+/// A packet or a value within one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    List(Vec<Expr>),
+    Int(usize),
+}
+
+fn parse_num(input: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    map_res(digit1, |num: &str| num.parse::<usize>().map(Expr::Int))(input)
+}
+
+fn parse_list(input: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    map(
+        delimited(
+            pair(tag("["), space0),
+            separated_list0(delimited(space0, tag(","), space0), parse_expr),
+            pair(space0, tag("]")),
+        ),
+        Expr::List,
+    )(input)
+}
+
+/// Parse a list or an integer
+pub fn parse_expr(input: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    alt((parse_num, parse_list))(input)
+}
+
+impl PartialOrd for Expr {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Expr {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Expr::Int(l), Expr::Int(r)) => l.cmp(r),
+            (Expr::List(l), Expr::List(r)) => l.cmp(r),
+            (l, Expr::List(r)) => slice::from_ref(l).cmp(r),
+            (Expr::List(l), r) => l[..].cmp(slice::from_ref(r)),
+        }
+    }
+}
+
+/// Compare two packets, recording each step at the given depth
+fn explain_into(left: &Expr, right: &Expr, depth: usize, lines: &mut Vec<String>) -> Ordering {
+    let indent = "  ".repeat(depth);
+    lines.push(format!("{}- Compare {} vs {}", indent, left, right));
+
+    let ordering = match (left, right) {
+        (Expr::Int(l), Expr::Int(r)) => l.cmp(r),
+        (Expr::List(l), Expr::List(r)) => {
+            let mut ordering = Ordering::Equal;
+            for (l, r) in l.iter().zip(r.iter()) {
+                ordering = explain_into(l, r, depth + 1, lines);
+                if ordering.is_ne() {
+                    // The deeper comparison already said why
+                    return ordering;
+                }
+            }
+            if ordering.is_eq() {
+                ordering = l.len().cmp(&r.len());
+                match ordering {
+                    Ordering::Less => lines.push(format!(
+                        "{}  - Left side ran out of items, so inputs are in the right order",
+                        indent
+                    )),
+                    Ordering::Greater => lines.push(format!(
+                        "{}  - Right side ran out of items, so inputs are not in the right order",
+                        indent
+                    )),
+                    Ordering::Equal => (),
+                }
+            }
+            return ordering;
+        }
+        (l @ Expr::Int(_), Expr::List(_)) => {
+            let converted = Expr::List(vec![l.clone()]);
+            lines.push(format!(
+                "{}  - Mixed types; convert left to {} and retry comparison",
+                indent, converted
+            ));
+            return explain_into(&converted, right, depth + 1, lines);
+        }
+        (Expr::List(_), r @ Expr::Int(_)) => {
+            let converted = Expr::List(vec![r.clone()]);
+            lines.push(format!(
+                "{}  - Mixed types; convert right to {} and retry comparison",
+                indent, converted
+            ));
+            return explain_into(left, &converted, depth + 1, lines);
+        }
+    };
+
+    match ordering {
+        Ordering::Less => lines.push(format!(
+            "{}  - Left side is smaller, so inputs are in the right order",
+            indent
+        )),
+        Ordering::Greater => lines.push(format!(
+            "{}  - Right side is smaller, so inputs are not in the right order",
+            indent
+        )),
+        Ordering::Equal => (),
+    }
+    ordering
+}
+
+/// Compare two packets the same way as `Ord`, also describing each step in the style of the
+/// puzzle
+pub fn explain_cmp(left: &Expr, right: &Expr) -> (Ordering, String) {
+    let mut lines = vec![];
+    let ordering = explain_into(left, right, 0, &mut lines);
+    (ordering, lines.join("\n"))
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Int(num) => write!(f, "{}", num),
+            Expr::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        all_consuming(delimited(space0, parse_expr, space0))(s)
+            .finish()
+            .map(|(_, expr)| expr)
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+impl From<&Expr> for Value {
+    fn from(expr: &Expr) -> Self {
+        match expr {
+            Expr::Int(num) => Value::from(*num),
+            Expr::List(items) => Value::Array(items.iter().map(Value::from).collect()),
+        }
+    }
+}
+
+impl From<Expr> for Value {
+    fn from(expr: Expr) -> Self {
+        Value::from(&expr)
+    }
+}
+
+impl TryFrom<&Value> for Expr {
+    type Error = Error;
+
+    /// Only arrays and non-negative integers can be packets
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Array(items) => items
+                .iter()
+                .map(Expr::try_from)
+                .collect::<Result<_, _>>()
+                .map(Expr::List),
+            Value::Number(num) => num
+                .as_u64()
+                .and_then(|num| usize::try_from(num).ok())
+                .map(Expr::Int)
+                .with_context(|| format!("{} is not a non-negative integer", num)),
+            other => bail!("{} is not an array or integer", other),
+        }
+    }
+}
+
+impl TryFrom<Value> for Expr {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        Expr::try_from(&value)
+    }
+}
+
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Value::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Expr::try_from(Value::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+/// Find the decoder key for the distress signal: the product of the positions (starting from 1)
+/// the divider packets would have if they were sorted in with the other packets.
+///
+/// Each position is found by counting the packets that come before the divider, so nothing has
+/// to be sorted.
+pub fn decoder_key<'a>(
+    packets: impl IntoIterator<Item = &'a Expr> + Clone,
+    dividers: &[Expr],
+) -> Result<usize, Error> {
+    if dividers.is_empty() {
+        bail!("No divider packets to locate");
+    }
+
+    dividers
+        .iter()
+        .enumerate()
+        .try_fold(1, |key, (i, divider)| {
+            if dividers[..i].contains(divider) {
+                bail!("Divider {} appears more than once", divider);
+            }
+
+            let before = packets
+                .clone()
+                .into_iter()
+                .filter(|packet| *packet < divider)
+                .count()
+                + dividers.iter().filter(|other| *other < divider).count();
+            Ok(key * (before + 1))
+        })
+}
+
+/// Reads packets one line at a time, skipping blank lines
+pub struct PacketReader<R> {
+    reader: R,
+    line: String,
+    /// How many lines have been read so far
+    line_number: usize,
+}
+
+impl<R: BufRead> PacketReader<R> {
+    pub fn new(reader: R) -> Self {
+        PacketReader {
+            reader,
+            line: String::new(),
+            line_number: 0,
+        }
+    }
+
+    /// Read the next line, trimmed, or `None` at the end of the input
+    fn read_line(&mut self) -> Option<Result<String, Error>> {
+        self.line.clear();
+        match self.reader.read_line(&mut self.line) {
+            Ok(0) => None,
+            Ok(_) => {
+                self.line_number += 1;
+                // Trimming also takes care of \r\n endings
+                Some(Ok(self.line.trim().to_owned()))
+            }
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+
+    /// Group the packets into the pairs separated by blank lines. Any number of blank lines can
+    /// separate pairs, but each group must have exactly two packets.
+    pub fn pairs(mut self) -> impl Iterator<Item = Result<(Expr, Expr), Error>> {
+        std::iter::from_fn(move || {
+            let mut group: Vec<Expr> = vec![];
+            let mut first_line = 0;
+            loop {
+                match self.read_line() {
+                    Some(Err(e)) => return Some(Err(e)),
+                    Some(Ok(line)) if !line.is_empty() => {
+                        if group.is_empty() {
+                            first_line = self.line_number;
+                        }
+                        match line.parse() {
+                            Ok(packet) => group.push(packet),
+                            Err(e) => return Some(Err(e)),
+                        }
+                    }
+                    // A blank line or the end of the input ends the group, if one has started
+                    end => {
+                        if !group.is_empty() || end.is_none() {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            match group.len() {
+                0 => None,
+                2 => {
+                    let right = group.pop()?;
+                    let left = group.pop()?;
+                    Some(Ok((left, right)))
+                }
+                n => Some(Err(anyhow!(
+                    "Expected a pair of packets in the group starting on line {}, found {}",
+                    first_line,
+                    n
+                ))),
+            }
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for PacketReader<R> {
+    type Item = Result<Expr, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.read_line()? {
+                Ok(packet) if packet.is_empty() => (),
+                Ok(packet) => return Some(packet.parse()),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() -> Result<(), Error> {
+        for packet in ["[]", "[[]]", "[1,[2,[3,[4,[5,6,7]]]],8,9]", "[10,[],[[4]]]"] {
+            let expr: Expr = packet.parse()?;
+            assert_eq!(expr.to_string(), packet);
+
+            let value = Value::from(&expr);
+            assert_eq!(serde_json::to_string(&value)?, packet);
+            assert_eq!(Expr::try_from(value)?, expr);
+            assert_eq!(serde_json::from_str::<Expr>(packet)?, expr);
+        }
+
+        assert!("[1,2".parse::<Expr>().is_err());
+        assert_eq!(
+            "[ 1 , [ ], [2 ,3] ]".parse::<Expr>()?.to_string(),
+            "[1,[],[2,3]]"
+        );
+        assert!(Expr::try_from(serde_json::json!([1, -2])).is_err());
+        assert!(Expr::try_from(serde_json::json!(["a"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn explanations() -> Result<(), Error> {
+        let (ordering, explanation) = explain_cmp(&"[[1],[2,3,4]]".parse()?, &"[[1],4]".parse()?);
+        assert_eq!(ordering, Ordering::Less);
+        assert_eq!(
+            explanation,
+            "- Compare [[1],[2,3,4]] vs [[1],4]
+  - Compare [1] vs [1]
+    - Compare 1 vs 1
+  - Compare [2,3,4] vs 4
+    - Mixed types; convert right to [4] and retry comparison
+    - Compare [2,3,4] vs [4]
+      - Compare 2 vs 4
+        - Left side is smaller, so inputs are in the right order"
+        );
+
+        let (ordering, explanation) = explain_cmp(&"[7,7,7,7]".parse()?, &"[7,7,7]".parse()?);
+        assert_eq!(ordering, Ordering::Greater);
+        assert!(explanation
+            .ends_with("\n  - Right side ran out of items, so inputs are not in the right order"));
+
+        let (ordering, _) = explain_cmp(&"[[[]]]".parse()?, &"[[]]".parse()?);
+        assert_eq!(ordering, Ordering::Greater);
+        Ok(())
+    }
+
+    #[test]
+    fn reader() -> Result<(), Error> {
+        let input = "\r\n[1, 2]\r\n[3]\r\n\r\n\r\n\r\n[[]]\n[4]\n\n";
+        let pairs = PacketReader::new(input.as_bytes())
+            .pairs()
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[1].0.to_string(), "[[]]");
+
+        let mut odd = PacketReader::new("[1]\n[2]\n\n[3]\n".as_bytes()).pairs();
+        assert!(odd.next().unwrap().is_ok());
+        assert!(odd.next().unwrap().is_err());
+
+        // A missing packet mustn't shift the pairs after it
+        let mut shifted =
+            PacketReader::new("[1]\n[2]\n\n[3]\n\n[4]\n[5]\n\n[6]\n".as_bytes()).pairs();
+        assert!(shifted.next().unwrap().is_ok());
+        assert_eq!(
+            shifted.next().unwrap().unwrap_err().to_string(),
+            "Expected a pair of packets in the group starting on line 4, found 1"
+        );
+
+        let mut crowded = PacketReader::new("[1]\n[2]\n[3]\n".as_bytes()).pairs();
+        assert_eq!(
+            crowded.next().unwrap().unwrap_err().to_string(),
+            "Expected a pair of packets in the group starting on line 1, found 3"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decoder_keys() -> Result<(), Error> {
+        let packets: Vec<Expr> = ["[1,1,3,1,1]", "[[1],[2,3,4]]", "[3]", "[[2]]"]
+            .iter()
+            .map(|p| p.parse())
+            .collect::<Result<_, _>>()?;
+        let dividers: Vec<Expr> = vec!["[[2]]".parse()?, "[[6]]".parse()?];
+
+        // [1,1,3,1,1], [[1],[2,3,4]], [[2]], [[2]], [3], [[6]]
+        assert_eq!(decoder_key(&packets, &dividers)?, 3 * 6);
+        assert_eq!(decoder_key(&packets, &dividers[1..])?, 5);
+        assert!(decoder_key(&packets, &[]).is_err());
+        assert!(decoder_key(&packets, &[dividers[0].clone(), dividers[0].clone()]).is_err());
+        Ok(())
+    }
+}
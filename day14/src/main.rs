@@ -1,171 +1,537 @@
 //! Day 14
 
-use std::{collections::HashSet, num::ParseIntError, str::FromStr};
+use std::{
+    collections::HashSet,
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use nom::{
     bytes::complete::tag,
     character::complete::{digit1, line_ending},
-    combinator::map_res,
+    combinator::{all_consuming, map, map_res, opt, recognize},
     error::VerboseError,
-    multi::separated_list1,
-    sequence::separated_pair,
-    IResult,
+    multi::{separated_list0, separated_list1},
+    sequence::{preceded, separated_pair},
+    Finish, IResult,
 };
 use point_2d::Point2D;
 use util::*;
 
+/// What lies beneath the cave's rock
+#[derive(Clone, Copy)]
+enum Floor {
+    /// Sand falling past the lowest rock is lost, as in part 1
+    Abyss,
+    /// An endless floor two below the lowest rock, as in part 2
+    BelowBottom,
+    /// An endless floor at the given depth. Nothing can pass through or below it.
+    At(isize),
+}
+
+/// Something filling a position in the cave
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Tile {
+    Rock,
+    /// Sand that has come to rest
+    Sand,
+    /// Somewhere sand has passed through on its way into the abyss
+    Falling,
+}
+
+/// A rectangle of tiles that grows to fit whatever is put in it
+#[derive(Clone, Default)]
+struct Grid {
+    /// The position of the top left tile
+    origin: Point2D<isize>,
+    width: isize,
+    height: isize,
+    tiles: Vec<Option<Tile>>,
+}
+
+impl Grid {
+    /// Where a position's tile is kept, if it's within the grid
+    fn index(&self, position: &Point2D<isize>) -> Option<usize> {
+        let Point2D(x, y) = position - &self.origin;
+        (x >= 0 && y >= 0 && x < self.width && y < self.height)
+            .then_some((y * self.width + x) as usize)
+    }
+
+    fn get(&self, position: &Point2D<isize>) -> Option<Tile> {
+        self.index(position).and_then(|i| self.tiles[i])
+    }
+
+    fn set(&mut self, position: Point2D<isize>, tile: Option<Tile>) {
+        if self.index(&position).is_none() {
+            if tile.is_none() {
+                return;
+            }
+            self.grow_to(position);
+        }
+        let i = self.index(&position).unwrap();
+        self.tiles[i] = tile;
+    }
+
+    /// Make room for a position, leaving some slack so repeated growth stays cheap
+    fn grow_to(&mut self, position: Point2D<isize>) {
+        let (old_min, old_max) = if self.tiles.is_empty() {
+            (position, position)
+        } else {
+            (
+                self.origin,
+                self.origin + Point2D(self.width - 1, self.height - 1),
+            )
+        };
+        let slack = Point2D(self.width / 2 + 1, self.height / 2 + 1);
+        let mut min = old_min;
+        let mut max = old_max;
+        if position.0 < min.0 {
+            min.0 = position.0 - slack.0;
+        }
+        if position.1 < min.1 {
+            min.1 = position.1 - slack.1;
+        }
+        if position.0 > max.0 {
+            max.0 = position.0 + slack.0;
+        }
+        if position.1 > max.1 {
+            max.1 = position.1 + slack.1;
+        }
+
+        let mut grown = Grid {
+            origin: min,
+            width: max.0 - min.0 + 1,
+            height: max.1 - min.1 + 1,
+            tiles: vec![],
+        };
+        grown.tiles = vec![None; (grown.width * grown.height) as usize];
+        for (position, tile) in self.iter() {
+            let i = grown.index(&position).unwrap();
+            grown.tiles[i] = Some(tile);
+        }
+        *self = grown;
+    }
+
+    /// Every filled position along with what fills it
+    fn iter(&self) -> impl Iterator<Item = (Point2D<isize>, Tile)> + '_ {
+        self.tiles.iter().enumerate().filter_map(|(i, tile)| {
+            let i = i as isize;
+            tile.map(|tile| (self.origin + Point2D(i % self.width, i / self.width), tile))
+        })
+    }
+}
+
 #[derive(Clone)]
 struct Cave {
-    occupied: HashSet<Point2D<isize>>,
+    tiles: Grid,
     bottom: isize,
+    /// Where sand pours in from
+    source: Point2D<isize>,
+    floor: Floor,
+    /// Where the last grain fell through on its way to rest. Every position on it is still open,
+    /// so the next grain can pick up from the end.
+    path: Vec<Point2D<isize>>,
+}
+
+/// Parse a signed integer with no separators
+fn parse_signed_int(input: &str) -> IResult<&str, isize, VerboseError<&str>> {
+    map_res(recognize(preceded(opt(tag("-")), digit1)), |num: &str| {
+        num.parse()
+    })(input)
 }
 
+/// Parse rock paths, one per line. Blank lines are read as empty paths.
 fn parse(input: &str) -> IResult<&str, Vec<Vec<Point2D<isize>>>, VerboseError<&str>> {
-    separated_list1(
+    all_consuming(separated_list1(
         line_ending,
-        separated_list1(
+        separated_list0(
             tag(" -> "),
-            map_res(
-                separated_pair(
-                    digit1::<&str, VerboseError<&str>>,
-                    tag(","),
-                    digit1::<&str, VerboseError<&str>>,
-                ),
-                |(x, y)| -> Result<Point2D<isize>, ParseIntError> {
-                    Ok(Point2D(x.parse()?, y.parse()?))
-                },
+            map(
+                separated_pair(parse_signed_int, tag(","), parse_signed_int),
+                |(x, y)| Point2D(x, y),
             ),
         ),
-    )(input)
-}
-
-fn draw_cave(instructions: &[Vec<Point2D<isize>>]) -> Cave {
-    let mut occupied: HashSet<Point2D<isize>> = HashSet::new();
-    let mut bottom = 0;
-    for path in instructions.iter() {
-        let mut iter = path.iter();
-        let mut prev = iter.next().unwrap();
-        occupied.insert(*prev);
-
-        for next in iter {
-            if prev.0 == next.0 {
-                let range = if prev.1 < next.1 {
-                    prev.1..=next.1
-                } else {
-                    next.1..=prev.1
-                };
-
-                for y in range {
-                    occupied.insert(Point2D(prev.0, y));
-                    bottom = bottom.max(y);
-                }
-            } else {
-                let range = if prev.0 < next.0 {
-                    prev.0..=next.0
-                } else {
-                    next.0..=prev.0
-                };
-
-                for x in range {
-                    occupied.insert(Point2D(x, prev.1));
-                }
-                bottom = bottom.max(prev.1);
+    ))(input)
+}
+
+/// Find every position on a straight line between two points, using Bresenham's algorithm
+fn segment(from: Point2D<isize>, to: Point2D<isize>) -> Vec<Point2D<isize>> {
+    let dx = (to.0 - from.0).abs();
+    let dy = -(to.1 - from.1).abs();
+    let step = Point2D((to.0 - from.0).signum(), (to.1 - from.1).signum());
+    let mut error = dx + dy;
+    let mut position = from;
+    let mut points = vec![position];
+
+    while position != to {
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            position.0 += step.0;
+        }
+        if doubled <= dx {
+            error += dx;
+            position.1 += step.1;
+        }
+        points.push(position);
+    }
+
+    points
+}
+
+/// Draw rock along each path. Diagonal segments are an error unless `rasterise_diagonals` is set,
+/// in which case they're drawn as the closest line of positions.
+fn draw_cave(
+    instructions: &[Vec<Point2D<isize>>],
+    rasterise_diagonals: bool,
+) -> Result<Cave, Error> {
+    let mut tiles = Grid::default();
+    let mut bottom = None;
+    for (i, path) in instructions.iter().enumerate() {
+        let mut rock = path.first().into_iter().copied().collect::<Vec<_>>();
+
+        for pair in path.windows(2) {
+            let (prev, next) = (pair[0], pair[1]);
+            if prev.0 != next.0 && prev.1 != next.1 && !rasterise_diagonals {
+                bail!(
+                    "Diagonal segment from {},{} to {},{} in path {}",
+                    prev.0,
+                    prev.1,
+                    next.0,
+                    next.1,
+                    i + 1
+                );
             }
+            rock.extend(segment(prev, next));
+        }
 
-            prev = next;
+        for position in rock {
+            tiles.set(position, Some(Tile::Rock));
+            bottom = bottom.max(Some(position.1));
         }
     }
-    Cave { occupied, bottom }
+
+    Ok(Cave {
+        tiles,
+        bottom: bottom.unwrap_or(0),
+        source: Point2D(500, 0),
+        floor: Floor::Abyss,
+        path: vec![],
+    })
+}
+
+/// Parse and draw a cave, see `draw_cave`
+fn parse_cave(input: &str, rasterise_diagonals: bool) -> Result<Cave, Error> {
+    let (_, instructions) = parse(input.trim_end())
+        .finish()
+        .map_err(|e| anyhow!("{}", e))?;
+    draw_cave(&instructions, rasterise_diagonals)
 }
 
 impl FromStr for Cave {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        parse(s)
-            .map(|instructions| draw_cave(&instructions.1))
-            .map_err(|e| anyhow!("{}", e))
+        parse_cave(s, false)
     }
 }
 
 impl Cave {
+    /// The depth of the floor, if there is one
+    fn floor_depth(&self) -> Option<isize> {
+        match self.floor {
+            Floor::Abyss => None,
+            Floor::BelowBottom => Some(self.bottom + 2),
+            Floor::At(depth) => Some(depth),
+        }
+    }
+
+    /// Check if sand can move into a position
+    fn is_open(&self, position: &Point2D<isize>) -> bool {
+        matches!(self.tiles.get(position), None | Some(Tile::Falling))
+            && self.floor_depth().is_none_or(|depth| position.1 < depth)
+    }
+
     /// Drops a grain of sand and returns true if it came to rest
     fn drop_sand(&mut self) -> bool {
-        let mut position: Point2D<isize> = Point2D(500, 0);
+        if self.path.first() != Some(&self.source) {
+            self.path = vec![self.source];
+        }
 
-        let candidate_moves: Vec<Point2D<isize>> =
-            vec![Point2D(0, 1), Point2D(-1, 1), Point2D(1, 1)];
+        if !self.is_open(&self.source) {
+            return false;
+        }
 
-        while let Some(next) = candidate_moves.iter().find_map(|m| {
-            let candidate = &position + m;
-            (!self.occupied.contains(&candidate)).then_some(candidate)
-        }) {
-            position = next;
+        let candidate_moves = [Point2D(0, 1), Point2D(-1, 1), Point2D(1, 1)];
 
-            if position.1 > self.bottom {
-                return false;
+        while let Some(&position) = self.path.last() {
+            match candidate_moves.iter().find_map(|m| {
+                let candidate = &position + m;
+                self.is_open(&candidate).then_some(candidate)
+            }) {
+                Some(next) => {
+                    self.path.push(next);
+                    if self.floor_depth().is_none() && next.1 > self.bottom {
+                        // Mark the way into the abyss
+                        for position in self.path.drain(1..) {
+                            self.tiles.set(position, Some(Tile::Falling));
+                        }
+                        return false;
+                    }
+                }
+                None => {
+                    self.tiles.set(position, Some(Tile::Sand));
+                    self.path.pop();
+                    return true;
+                }
             }
         }
 
-        self.occupied.insert(position);
+        false
+    }
 
-        true
+    /// How many grains of sand have come to rest
+    fn sand_count(&self) -> usize {
+        self.tiles
+            .iter()
+            .filter(|(_, tile)| *tile == Tile::Sand)
+            .count()
     }
 
-    /// Drops a grain of sand and returns true if it came to rest
-    fn drop_sand_with_floor(&mut self) -> bool {
-        let mut position: Point2D<isize> = Point2D(500, 0);
+    /// How many positions are filled with rock
+    fn rock_count(&self) -> usize {
+        self.tiles
+            .iter()
+            .filter(|(_, tile)| *tile == Tile::Rock)
+            .count()
+    }
 
-        if self.occupied.contains(&position) {
-            return false;
+    /// Take out all of the sand, leaving only rock
+    fn clear_sand(&mut self) {
+        let sand: Vec<Point2D<isize>> = self
+            .tiles
+            .iter()
+            .filter_map(|(position, tile)| (tile != Tile::Rock).then_some(position))
+            .collect();
+        for position in sand {
+            self.tiles.set(position, None);
         }
+        self.path.clear();
+    }
 
-        let candidate_moves: Vec<Point2D<isize>> =
-            vec![Point2D(0, 1), Point2D(-1, 1), Point2D(1, 1)];
+    /// Drops sand until it stops coming to rest, returning the number of grains that did
+    fn fill(&mut self) -> usize {
+        let mut grains = 0;
+        while self.drop_sand() {
+            grains += 1;
+        }
+        grains
+    }
 
-        while let Some(next) = candidate_moves.iter().find_map(|m| {
-            let candidate = &position + m;
-            (!self.occupied.contains(&candidate)).then_some(candidate)
-        }) {
-            position = next;
+    /// Counts how many grains would come to rest before the source is blocked, without dropping
+    /// them one by one. Sand fills every open position it can reach falling straight or
+    /// diagonally down from the source, so each row can be worked out from the one above.
+    fn fill_count(&self) -> Result<usize, Error> {
+        let floor = self
+            .floor_depth()
+            .context("Sand never stops falling into the abyss")?;
 
-            if position.1 == self.bottom + 1 {
-                break;
-            }
+        let mut row: HashSet<isize> = HashSet::new();
+        if self.is_open(&self.source) {
+            row.insert(self.source.0);
         }
 
-        self.occupied.insert(position);
+        let mut grains = 0;
+        let mut y = self.source.1;
+        while !row.is_empty() && y < floor {
+            grains += row.len();
+            y += 1;
+            row = row
+                .iter()
+                .flat_map(|x| [x - 1, *x, x + 1])
+                .filter(|x| self.is_open(&Point2D(*x, y)))
+                .collect();
+        }
 
-        true
+        Ok(grains)
     }
 }
 
-fn part1(cave: &mut Cave) -> usize {
-    let mut grains = 0;
-    loop {
-        if !cave.drop_sand() {
-            break;
+/// Colors for each kind of tile in image frames
+const AIR_COLOR: [u8; 3] = [24, 24, 32];
+const ROCK_COLOR: [u8; 3] = [112, 112, 112];
+const SAND_COLOR: [u8; 3] = [220, 190, 110];
+const FALLING_COLOR: [u8; 3] = [110, 95, 55];
+const SOURCE_COLOR: [u8; 3] = [220, 60, 40];
+
+impl Cave {
+    /// The top left and bottom right corners of everything in the cave
+    fn bounds(&self) -> (Point2D<isize>, Point2D<isize>) {
+        let mut min = self.source;
+        let mut max = self.source;
+        for (point, _) in self.tiles.iter() {
+            min = Point2D(min.0.min(point.0), min.1.min(point.1));
+            max = Point2D(max.0.max(point.0), max.1.max(point.1));
         }
-        grains += 1;
+        if let Some(depth) = self.floor_depth() {
+            max.1 = max.1.max(depth);
+        }
+        (min, max)
     }
-    grains
-}
 
-fn part2(cave: &mut Cave) -> usize {
-    let mut grains = 0;
-    loop {
-        if !cave.drop_sand_with_floor() {
-            break;
+    /// The character drawn for a position, as in the puzzle's diagrams
+    fn tile(&self, position: &Point2D<isize>) -> char {
+        if *position == self.source {
+            return '+';
+        }
+        match self.tiles.get(position) {
+            Some(Tile::Rock) => '#',
+            Some(Tile::Sand) => 'o',
+            Some(Tile::Falling) => '~',
+            None if self.floor_depth() == Some(position.1) => '#',
+            None => '.',
+        }
+    }
+
+    /// Draw the cave within some bounds, one line per row
+    fn render_within(&self, (min, max): (Point2D<isize>, Point2D<isize>)) -> String {
+        (min.1..=max.1)
+            .map(|y| {
+                (min.0..=max.0)
+                    .map(|x| self.tile(&Point2D(x, y)))
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Draw everything in the cave
+    fn render(&self) -> String {
+        self.render_within(self.bounds())
+    }
+
+    /// Write the cave within some bounds as a binary PPM image, with each tile `scale` pixels wide
+    fn write_ppm(
+        &self,
+        out: &mut impl Write,
+        (min, max): (Point2D<isize>, Point2D<isize>),
+        scale: usize,
+    ) -> io::Result<()> {
+        let width = (max.0 - min.0 + 1) as usize * scale;
+        let height = (max.1 - min.1 + 1) as usize * scale;
+        write!(out, "P6\n{} {}\n255\n", width, height)?;
+
+        for y in min.1..=max.1 {
+            let row: Vec<u8> = (min.0..=max.0)
+                .flat_map(|x| {
+                    let color = match self.tile(&Point2D(x, y)) {
+                        '#' => ROCK_COLOR,
+                        'o' => SAND_COLOR,
+                        '~' => FALLING_COLOR,
+                        '+' => SOURCE_COLOR,
+                        _ => AIR_COLOR,
+                    };
+                    color.repeat(scale)
+                })
+                .collect();
+            for _ in 0..scale {
+                out.write_all(&row)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drop sand like `fill`, saving a numbered PPM frame to a directory every `every` grains and
+    /// once more at the end. Every frame covers the area of the final one, so they can be
+    /// stitched into an animation.
+    fn fill_with_frames(&mut self, dir: &Path, every: usize) -> Result<usize, Error> {
+        if every == 0 {
+            bail!("Frames need to be at least one grain apart");
+        }
+
+        let mut finished = self.clone();
+        finished.fill();
+        let bounds = finished.bounds();
+
+        fs::create_dir_all(dir)?;
+        let save = |cave: &Cave, frame: usize| -> Result<(), Error> {
+            let path = dir.join(format!("frame_{:05}.ppm", frame));
+            let mut file = io::BufWriter::new(fs::File::create(&path)?);
+            cave.write_ppm(&mut file, bounds, 4)
+                .with_context(|| format!("Couldn't write {}", path.display()))
+        };
+
+        let mut grains = 0;
+        let mut frame = 0;
+        while self.drop_sand() {
+            grains += 1;
+            if grains % every == 0 {
+                save(self, frame)?;
+                frame += 1;
+            }
         }
-        grains += 1;
+        save(self, frame)?;
+
+        Ok(grains)
     }
-    grains
+}
+
+fn part1(cave: &mut Cave) -> usize {
+    cave.floor = Floor::Abyss;
+    cave.fill()
+}
+
+fn part2(cave: &mut Cave) -> Result<usize, Error> {
+    cave.floor = Floor::BelowBottom;
+    cave.fill_count()
 }
 
 fn main() -> Result<(), Error> {
-    let mut cave: Cave = read_stdin()?.parse()?;
+    let rasterise_diagonals = env::args().any(|arg| arg == "--diagonals");
+    let mut cave = parse_cave(&read_stdin()?, rasterise_diagonals)?;
     println!("Part 1: {}", part1(&mut cave.clone()));
-    println!("Part 2: {}", part2(&mut cave));
+    println!("Part 2: {}", part2(&mut cave.clone())?);
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Show where the sand ends up in each part
+            "--render" => {
+                let mut filled = cave.clone();
+                for floor in [Floor::Abyss, Floor::BelowBottom] {
+                    filled.clear_sand();
+                    filled.floor = floor;
+                    filled.fill();
+                    println!(
+                        "\n{} sand resting on {} rock\n{}",
+                        filled.sand_count(),
+                        filled.rock_count(),
+                        filled.render()
+                    );
+                }
+            }
+            // Save frames of part 2 filling up
+            "--frames" => {
+                let dir: PathBuf = args.next().context("No frame directory")?.into();
+                let every = args.next().map_or(Ok(100), |n| n.parse())?;
+                cave.floor = Floor::BelowBottom;
+                cave.fill_with_frames(&dir, every)?;
+            }
+            // Count the sand resting on a floor at any depth
+            "--floor" => {
+                let depth = args.next().context("No floor depth")?.parse()?;
+                let mut floored = cave.clone();
+                floored.floor = Floor::At(depth);
+                println!("Floor at {}: {} sand", depth, floored.fill_count()?);
+            }
+            "--diagonals" => (),
+            _ => bail!("Unknown argument {}", arg),
+        }
+    }
+
     Ok(())
 }
 
@@ -183,7 +549,104 @@ mod tests {
 
     #[test]
     fn part2_example() -> Result<(), Error> {
-        assert_eq!(part2(&mut SAMPLE.parse()?), 93);
+        assert_eq!(part2(&mut SAMPLE.parse()?)?, 93);
+        Ok(())
+    }
+
+    #[test]
+    fn floors_and_sources() -> Result<(), Error> {
+        let mut cave: Cave = SAMPLE.parse()?;
+        cave.floor = Floor::BelowBottom;
+        assert_eq!(cave.clone().fill(), 93);
+
+        cave.floor = Floor::At(7);
+        assert_eq!(cave.fill_count()?, cave.clone().fill());
+
+        cave.source = Point2D(497, 2);
+        assert_eq!(cave.fill_count()?, cave.clone().fill());
+
+        // A floor above the source leaves no room for sand at all
+        cave.floor = Floor::At(-1);
+        assert_eq!(cave.fill_count()?, 0);
+        assert_eq!(cave.clone().fill(), 0);
+
+        cave.floor = Floor::Abyss;
+        assert!(cave.fill_count().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rendering() -> Result<(), Error> {
+        let mut cave: Cave = SAMPLE.parse()?;
+        assert_eq!(
+            cave.render(),
+            "......+...
+..........
+..........
+..........
+....#...##
+....#...#.
+..###...#.
+........#.
+........#.
+#########."
+        );
+
+        part1(&mut cave);
+        assert_eq!(
+            cave.render(),
+            ".......+...
+.......~...
+......~o...
+.....~ooo..
+....~#ooo##
+...~o#ooo#.
+..~###ooo#.
+..~..oooo#.
+.~o.ooooo#.
+~#########.
+~.........."
+        );
+
+        let mut image = vec![];
+        cave.write_ppm(&mut image, cave.bounds(), 2)?;
+        assert!(image.starts_with(b"P6\n22 22\n255\n"));
+        assert_eq!(image.len(), "P6\n22 22\n255\n".len() + 22 * 22 * 3);
+        Ok(())
+    }
+
+    #[test]
+    fn tiles() -> Result<(), Error> {
+        let mut cave: Cave = SAMPLE.parse()?;
+        assert_eq!(cave.rock_count(), 20);
+        part1(&mut cave);
+        assert_eq!(cave.sand_count(), 24);
+        assert_eq!(cave.rock_count(), 20);
+
+        cave.clear_sand();
+        assert_eq!(cave.sand_count(), 0);
+        assert_eq!(part2(&mut cave)?, 93);
+        cave.fill();
+        assert_eq!(cave.sand_count(), 93);
+        Ok(())
+    }
+
+    #[test]
+    fn path_parsing() -> Result<(), Error> {
+        let cave: Cave = "-2,-1 -> 2,-1\n\n-2,-3 -> -2,-1\n".parse()?;
+        assert_eq!(cave.rock_count(), 7);
+        assert_eq!(cave.bottom, -1);
+
+        let diagonal = "498,4 -> 500,6\n502,4 -> 502,9";
+        assert_eq!(
+            diagonal.parse::<Cave>().err().map(|e| e.to_string()),
+            Some("Diagonal segment from 498,4 to 500,6 in path 1".to_owned())
+        );
+        let cave = parse_cave(diagonal, true)?;
+        assert_eq!(cave.tiles.get(&Point2D(499, 5)), Some(Tile::Rock));
+        assert_eq!(cave.rock_count(), 9);
+
+        assert!("498,4 -> 498".parse::<Cave>().is_err());
         Ok(())
     }
 }
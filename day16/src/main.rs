@@ -1,8 +1,9 @@
 //! Day 16
 
 use std::{
-    cmp::Ordering,
-    collections::{BinaryHeap, HashMap, HashSet},
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+    env,
     str::FromStr,
 };
 
@@ -69,6 +70,48 @@ fn parse_valve_list(input: &str) -> Result<HashMap<String, Room>, Error> {
         .collect()
 }
 
+/// Check that every tunnel leads somewhere, tunnels go both ways, and every valve can be reached
+/// from the start
+fn validate(rooms: &HashMap<String, Room>, start: &str) -> Result<(), Error> {
+    let mut problems = vec![];
+
+    if !rooms.contains_key(start) {
+        problems.push(format!("Start valve {} is not defined", start));
+    }
+
+    let mut names: Vec<&String> = rooms.keys().collect();
+    names.sort();
+
+    for name in names.iter() {
+        for tunnel in rooms[*name].tunnels.iter() {
+            match rooms.get(tunnel) {
+                None => problems.push(format!(
+                    "Valve {} has a tunnel to undefined valve {}",
+                    name, tunnel
+                )),
+                Some(other) if !other.tunnels.contains(name) => problems.push(format!(
+                    "Valve {} has a tunnel to valve {} with no tunnel back",
+                    name, tunnel
+                )),
+                _ => (),
+            }
+        }
+    }
+
+    if rooms.contains_key(start) {
+        let reachable = distances_from(start.to_owned(), rooms);
+        for name in names.iter().filter(|name| !reachable.contains_key(**name)) {
+            problems.push(format!("Valve {} can't be reached from {}", name, start));
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!("Invalid valve list:\n{}", problems.join("\n"));
+    }
+
+    Ok(())
+}
+
 #[derive(Clone, Eq, PartialEq)]
 struct State {
     cost: usize,
@@ -109,6 +152,8 @@ fn distances_from(current: String, rooms: &HashMap<String, Room>) -> HashMap<Str
             }
         })
         .collect();
+    // The start may not be a defined valve itself
+    distances.insert(current.clone(), 0);
 
     let mut heap = BinaryHeap::new();
     heap.push(State {
@@ -119,20 +164,28 @@ fn distances_from(current: String, rooms: &HashMap<String, Room>) -> HashMap<Str
     // Adapted from the Djikstra example in the std::collections::binary_heap docs
     while let Some(State { cost, position }) = heap.pop() {
         // Important as we may have already found a better way
-        if cost > *distances.get(&position).unwrap() {
+        if cost > distances[&position] {
             continue;
         }
 
+        // Tunnels into undefined valves lead nowhere
+        let Some(room) = rooms.get(&position) else {
+            continue;
+        };
+
         // For each node we can reach, see if we can find a way with
         // a lower cost going through this node
-        for edge in rooms.get(&position).unwrap().tunnels.iter() {
+        for edge in room.tunnels.iter() {
             let next = State {
                 cost: cost + 1,
                 position: edge.clone(),
             };
 
             // If so, add it to the frontier and continue
-            if next.cost < *distances.get(&next.position).unwrap() {
+            if distances
+                .get(&next.position)
+                .is_some_and(|&distance| next.cost < distance)
+            {
                 // Relaxation, we have now found a better way
                 distances.insert(next.position.clone(), next.cost);
                 heap.push(next);
@@ -140,127 +193,569 @@ fn distances_from(current: String, rooms: &HashMap<String, Room>) -> HashMap<Str
         }
     }
 
+    // Leave out anything that can't be reached at all
+    distances.retain(|_, distance| *distance != usize::MAX);
     distances
 }
 
-fn best_case_release(
-    rooms: &HashMap<String, Room>,
-    distances: &mut HashMap<String, HashMap<String, usize>>,
-    current: String,
-    targets: HashSet<String>,
-    time_remaining: usize,
-) -> usize {
-    if targets.is_empty() || time_remaining == 0 {
-        return 0;
-    }
-
-    let current_distances = distances
-        .entry(current.clone())
-        .or_insert_with(|| distances_from(current.clone(), rooms))
-        .clone();
-
-    targets
-        .iter()
-        .map(|target| {
-            // Time to walk to the target and turn it on
-            let time_needed = current_distances.get(target).unwrap() + 1;
-            if time_needed > time_remaining {
-                return 0;
+/// Find the rooms passed through on a shortest walk between two rooms, including the destination
+fn path_between(from: &str, to: &str, rooms: &HashMap<String, Room>) -> Vec<String> {
+    let mut previous: HashMap<&str, &str> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+
+    while let Some(position) = queue.pop_front() {
+        if position == to {
+            break;
+        }
+
+        if let Some(room) = rooms.get(position) {
+            for edge in room.tunnels.iter() {
+                if edge != from && !previous.contains_key(edge.as_str()) {
+                    previous.insert(edge, position);
+                    queue.push_back(edge);
+                }
             }
+        }
+    }
 
-            let new_time_remaining = time_remaining - time_needed;
+    let mut path = vec![];
+    let mut position = to;
+    while position != from {
+        path.push(position.to_owned());
+        match previous.get(position) {
+            Some(prev) => position = prev,
+            None => return vec![],
+        }
+    }
+    path.reverse();
+    path
+}
 
-            let mut new_targets = targets.clone();
-            new_targets.remove(target);
+/// Configuration for a team of actors opening valves
+struct ValvePlan {
+    /// The valve every actor starts at
+    start: String,
+    /// Minutes until the volcano erupts
+    time: usize,
+    /// How many actors are opening valves
+    actors: usize,
+    /// Minutes each actor loses to training before it can set out
+    training_time: usize,
+}
 
-            let strength = rooms.get(target).unwrap().flow_rate * new_time_remaining;
+impl Default for ValvePlan {
+    fn default() -> Self {
+        ValvePlan {
+            start: "AA".to_owned(),
+            time: 30,
+            actors: 1,
+            training_time: 0,
+        }
+    }
+}
 
-            strength
-                + best_case_release(
-                    rooms,
-                    distances,
-                    target.clone(),
-                    new_targets,
-                    new_time_remaining,
-                )
-        })
-        .max()
-        .unwrap_or(0)
+/// An actor moving through the tunnels
+#[derive(Clone)]
+struct Actor {
+    id: usize,
+    position: String,
+    time_remaining: usize,
 }
 
-// Warning: slow
-fn best_case_release_2(
-    rooms: &HashMap<String, Room>,
-    distances: &mut HashMap<String, HashMap<String, usize>>,
-    targets: HashSet<String>,
-    mut actors: Vec<(String, usize)>,
-) -> usize {
-    if targets.is_empty() || actors.iter().all(|(_, time)| *time == 0) {
-        return 0;
-    }
-
-    actors.sort_by_key(|a| a.1);
-    actors.reverse();
-
-    actors
-        .iter()
-        .enumerate()
-        .find_map(|(actor_index, (current, time_remaining))| {
-            let current_distances = distances
-                .entry(current.clone())
-                .or_insert_with(|| distances_from(current.clone(), rooms))
-                .clone();
+/// A valve opened by an actor
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Opening {
+    actor: usize,
+    valve: String,
+    /// Minutes left once the valve is open
+    time_remaining: usize,
+}
+
+/// The pressure released by a sequence of valve openings
+#[derive(Clone, Debug, Default)]
+struct Release {
+    pressure: usize,
+    openings: Vec<Opening>,
+}
+
+/// A step in a single actor's schedule
+#[derive(Debug, Eq, PartialEq)]
+struct Step {
+    valve: String,
+    /// The minute the valve was opened in
+    minute: usize,
+    /// Total pressure released by the valves this actor has opened so far
+    cumulative_pressure: usize,
+}
+
+impl ValvePlan {
+    /// Minutes each actor has after training
+    fn actor_time(&self) -> usize {
+        self.time.saturating_sub(self.training_time)
+    }
 
+    /// Find the most pressure the actors can release working together, along with how much
+    /// searching it took
+    fn best_release_with_stats(
+        &self,
+        rooms: &HashMap<String, Room>,
+        distances: &mut HashMap<String, HashMap<String, usize>>,
+    ) -> (Release, SearchStats) {
+        let targets: HashSet<String> = rooms
+            .values()
+            .filter_map(|r| (r.flow_rate > 0).then_some(&r.name))
+            .cloned()
+            .collect();
+        let actors = (0..self.actors)
+            .map(|id| Actor {
+                id,
+                position: self.start.clone(),
+                time_remaining: self.actor_time(),
+            })
+            .collect();
+
+        // The quickest any valve could be reached and opened, used for the optimistic bound
+        let min_step = if targets.contains(&self.start) {
+            1
+        } else {
             targets
                 .iter()
-                .filter_map(|target| {
-                    // Time to walk to the target and turn it on
-                    let time_needed = current_distances.get(target).unwrap() + 1;
-                    if time_needed > *time_remaining {
-                        return None;
-                    }
+                .chain([&self.start])
+                .flat_map(|from| {
+                    let from_distances = distances
+                        .entry(from.clone())
+                        .or_insert_with(|| distances_from(from.clone(), rooms))
+                        .clone();
+                    targets
+                        .iter()
+                        .filter(move |to| *to != from)
+                        .filter_map(move |to| from_distances.get(to).copied())
+                })
+                .min()
+                .unwrap_or(0)
+                + 1
+        };
 
-                    let new_time_remaining = time_remaining - time_needed;
+        let mut search = Search {
+            rooms,
+            distances,
+            min_step,
+            best: Release::default(),
+            stats: SearchStats::default(),
+        };
+        search.best_case_release(targets, actors, &mut Release::default());
+        (search.best, search.stats)
+    }
 
-                    let mut new_targets = targets.clone();
-                    new_targets.remove(target);
+    /// Split a release into the valves each actor opened, in order
+    fn schedule(&self, rooms: &HashMap<String, Room>, release: &Release) -> Vec<Vec<Step>> {
+        let mut schedules: Vec<Vec<Step>> = (0..self.actors).map(|_| vec![]).collect();
+
+        for opening in release.openings.iter() {
+            let steps = &mut schedules[opening.actor];
+            let pressure = rooms
+                .get(&opening.valve)
+                .map_or(0, |r| r.flow_rate * opening.time_remaining);
+            let cumulative_pressure =
+                steps.last().map_or(0, |s: &Step| s.cumulative_pressure) + pressure;
+            steps.push(Step {
+                valve: opening.valve.clone(),
+                minute: self.actor_time() - opening.time_remaining,
+                cumulative_pressure,
+            });
+        }
 
-                    let strength = rooms.get(target).unwrap().flow_rate * new_time_remaining;
+        schedules
+    }
 
-                    let mut new_actors = actors.clone();
+    /// List every room each actor walks through, starting from the start room
+    fn routes(&self, rooms: &HashMap<String, Room>, release: &Release) -> Vec<Vec<String>> {
+        self.schedule(rooms, release)
+            .iter()
+            .map(|steps| {
+                let mut route = vec![self.start.clone()];
+                for step in steps.iter() {
+                    let path = path_between(route.last().unwrap(), &step.valve, rooms);
+                    route.extend(path);
+                }
+                route
+            })
+            .collect()
+    }
 
-                    new_actors[actor_index] = (target.clone(), new_time_remaining);
+    /// What to call an actor in a transcript
+    fn actor_name(&self, actor: usize) -> (String, &'static str, &'static str) {
+        match (actor, self.actors) {
+            (0, _) => ("You".to_owned(), "move", "open"),
+            (1, 2) => ("The elephant".to_owned(), "moves", "opens"),
+            (n, _) => (format!("Elephant {}", n), "moves", "opens"),
+        }
+    }
 
-                    Some(strength + best_case_release_2(rooms, distances, new_targets, new_actors))
-                })
-                .max()
-        })
-        .unwrap_or(0)
+    /// Describe a release minute by minute, in the style of the puzzle
+    fn transcript(&self, rooms: &HashMap<String, Room>, release: &Release) -> String {
+        let time = self.actor_time();
+
+        // What each actor does in each minute
+        let mut actions: Vec<Vec<String>> = vec![vec![String::new(); time]; self.actors];
+        // The valves that were opened in each minute
+        let mut opened: Vec<Vec<&str>> = vec![vec![]; time];
+
+        let schedules = self.schedule(rooms, release);
+        for (actor, steps) in schedules.iter().enumerate() {
+            let (name, move_verb, open_verb) = self.actor_name(actor);
+            let mut position = self.start.as_str();
+            let mut minute = 0;
+
+            for step in steps.iter() {
+                for room in path_between(position, &step.valve, rooms) {
+                    actions[actor][minute] = format!("{} {} to valve {}.", name, move_verb, room);
+                    minute += 1;
+                }
+                actions[actor][step.minute - 1] =
+                    format!("{} {} valve {}.", name, open_verb, step.valve);
+                opened[step.minute - 1].push(&step.valve);
+                position = &step.valve;
+                minute = step.minute;
+            }
+        }
+
+        let mut open: Vec<&str> = vec![];
+        let mut minutes = vec![];
+        for minute in 0..time {
+            let pressure: usize = open
+                .iter()
+                .filter_map(|valve| rooms.get(*valve))
+                .map(|r| r.flow_rate)
+                .sum();
+            let status = match open.as_slice() {
+                [] => "No valves are open.".to_owned(),
+                [valve] => format!("Valve {} is open, releasing {} pressure.", valve, pressure),
+                [valve_a, valve_b] => format!(
+                    "Valves {} and {} are open, releasing {} pressure.",
+                    valve_a, valve_b, pressure
+                ),
+                [rest @ .., last] => format!(
+                    "Valves {}, and {} are open, releasing {} pressure.",
+                    rest.join(", "),
+                    last,
+                    pressure
+                ),
+            };
+
+            let mut lines = vec![format!("== Minute {} ==", minute + 1), status];
+            lines.extend(
+                actions
+                    .iter()
+                    .map(|a| &a[minute])
+                    .filter(|a| !a.is_empty())
+                    .cloned(),
+            );
+            minutes.push(lines.join("\n"));
+
+            open.extend(opened[minute].iter());
+            open.sort();
+        }
+
+        minutes.join("\n\n")
+    }
 }
 
-fn solutions(rooms: &HashMap<String, Room>) -> (usize, usize) {
-    let targets: HashSet<String> = rooms
+/// Find the shortest walk through zero-flow rooms from a room to each other kept room
+fn corridors_from(
+    current: &str,
+    rooms: &HashMap<String, Room>,
+    kept: &HashSet<&str>,
+) -> HashMap<String, usize> {
+    let mut seen = HashSet::from([current]);
+    let mut queue = VecDeque::from([(current, 0)]);
+    let mut reached = HashMap::new();
+
+    while let Some((position, cost)) = queue.pop_front() {
+        // Don't walk through rooms that are staying in the graph
+        if position != current && kept.contains(position) {
+            reached.insert(position.to_owned(), cost);
+            continue;
+        }
+
+        if let Some(room) = rooms.get(position) {
+            for edge in room.tunnels.iter() {
+                if seen.insert(edge) {
+                    queue.push_back((edge, cost + 1));
+                }
+            }
+        }
+    }
+
+    reached
+}
+
+/// Render the tunnel network as a Graphviz graph, optionally replacing zero-flow rooms with
+/// weighted edges and highlighting the rooms each actor walked through
+fn to_dot(
+    rooms: &HashMap<String, Room>,
+    start: &str,
+    collapse: bool,
+    routes: Option<&[Vec<String>]>,
+) -> String {
+    let kept: HashSet<&str> = rooms
         .values()
-        .filter_map(|r| (r.flow_rate > 0).then_some(&r.name))
-        .cloned()
+        .filter(|r| !collapse || r.flow_rate > 0 || r.name == start)
+        .map(|r| r.name.as_str())
         .collect();
+
+    // Edges are undirected, so store them with the names in order
+    let mut edges: HashMap<(String, String), usize> = HashMap::new();
+    for name in kept.iter() {
+        let reached: HashMap<String, usize> = if collapse {
+            corridors_from(name, rooms, &kept)
+        } else {
+            rooms[*name]
+                .tunnels
+                .iter()
+                .map(|t| (t.clone(), 1))
+                .collect()
+        };
+        for (other, cost) in reached {
+            let key = if **name < *other {
+                (name.to_string(), other)
+            } else {
+                (other, name.to_string())
+            };
+            let weight = edges.entry(key).or_insert(cost);
+            *weight = cost.min(*weight);
+        }
+    }
+
+    let mut route_rooms: HashSet<&str> = HashSet::new();
+    let mut route_edges: HashSet<(&str, &str)> = HashSet::new();
+    for route in routes.unwrap_or_default() {
+        let stops: Vec<&str> = route
+            .iter()
+            .map(String::as_str)
+            .filter(|room| kept.contains(room))
+            .collect();
+        route_rooms.extend(stops.iter());
+        for pair in stops.windows(2) {
+            route_edges.insert((pair[0].min(pair[1]), pair[0].max(pair[1])));
+        }
+    }
+
+    let mut names: Vec<&str> = kept.iter().copied().collect();
+    names.sort();
+    let mut edge_list: Vec<_> = edges.into_iter().collect();
+    edge_list.sort();
+
+    let mut lines = vec!["graph valves {".to_owned()];
+    for name in names {
+        let highlight = if route_rooms.contains(name) {
+            ", color=red"
+        } else {
+            ""
+        };
+        lines.push(format!(
+            "    {} [label=\"{} ({})\"{}];",
+            name, name, rooms[name].flow_rate, highlight
+        ));
+    }
+    for ((a, b), weight) in edge_list {
+        let mut attributes = vec![];
+        if collapse {
+            attributes.push(format!("label=\"{}\"", weight));
+        }
+        if route_edges.contains(&(a.as_str(), b.as_str())) {
+            attributes.push("color=red".to_owned());
+            attributes.push("penwidth=2".to_owned());
+        }
+        if attributes.is_empty() {
+            lines.push(format!("    {} -- {};", a, b));
+        } else {
+            lines.push(format!("    {} -- {} [{}];", a, b, attributes.join(", ")));
+        }
+    }
+    lines.push("}".to_owned());
+
+    lines.join("\n")
+}
+
+/// How much of the search tree was visited
+#[derive(Debug, Default)]
+struct SearchStats {
+    /// Nodes whose moves were considered
+    explored: usize,
+    /// Nodes skipped because they couldn't beat the best release found
+    pruned: usize,
+}
+
+/// A branch-and-bound search for the best release
+struct Search<'a> {
+    rooms: &'a HashMap<String, Room>,
+    distances: &'a mut HashMap<String, HashMap<String, usize>>,
+    /// The fewest minutes it could take to walk to and open any valve
+    min_step: usize,
+    best: Release,
+    stats: SearchStats,
+}
+
+impl Search<'_> {
+    /// An optimistic estimate of the pressure still to be released, assuming every valve is
+    /// reached as quickly as possible and the strongest valves are opened first
+    fn upper_bound(&self, targets: &HashSet<String>, actors: &[Actor]) -> usize {
+        let mut flows: Vec<usize> = targets
+            .iter()
+            .filter_map(|t| self.rooms.get(t))
+            .map(|r| r.flow_rate)
+            .collect();
+        flows.sort_unstable();
+
+        let mut times: Vec<usize> = actors.iter().map(|a| a.time_remaining).collect();
+        let mut bound = 0;
+
+        for flow in flows.into_iter().rev() {
+            // Whoever has the most time left opens the next strongest valve
+            let time = times.iter_mut().max().unwrap();
+            *time = time.saturating_sub(self.min_step);
+            if *time == 0 {
+                break;
+            }
+            bound += flow * *time;
+        }
+
+        bound
+    }
+
+    // Warning: slow with multiple actors
+    fn best_case_release(
+        &mut self,
+        targets: HashSet<String>,
+        mut actors: Vec<Actor>,
+        current: &mut Release,
+    ) {
+        if current.pressure > self.best.pressure {
+            self.best = current.clone();
+        }
+
+        if targets.is_empty() || actors.iter().all(|a| a.time_remaining == 0) {
+            return;
+        }
+
+        if current.pressure + self.upper_bound(&targets, &actors) <= self.best.pressure {
+            self.stats.pruned += 1;
+            return;
+        }
+
+        self.stats.explored += 1;
+
+        // The actor with the most time left always moves next
+        actors.sort_by_key(|a| Reverse(a.time_remaining));
+        let actor = actors[0].clone();
+
+        let current_distances = self
+            .distances
+            .entry(actor.position.clone())
+            .or_insert_with(|| distances_from(actor.position.clone(), self.rooms))
+            .clone();
+
+        // (target, time remaining once opened, pressure released)
+        let mut moves: Vec<(&String, usize, usize)> = targets
+            .iter()
+            .filter_map(|target| {
+                // Time to walk to the target and turn it on
+                let time_needed = current_distances.get(target)? + 1;
+                if time_needed > actor.time_remaining {
+                    return None;
+                }
+
+                let new_time_remaining = actor.time_remaining - time_needed;
+                let strength = self.rooms.get(target).unwrap().flow_rate * new_time_remaining;
+                Some((target, new_time_remaining, strength))
+            })
+            .collect();
+
+        // Trying the strongest moves first finds good releases early, so more gets pruned
+        moves.sort_by_key(|m| Reverse(m.2));
+
+        for (target, new_time_remaining, strength) in moves {
+            let mut new_targets = targets.clone();
+            new_targets.remove(target);
+
+            let mut new_actors = actors.clone();
+            new_actors[0] = Actor {
+                id: actor.id,
+                position: target.clone(),
+                time_remaining: new_time_remaining,
+            };
+
+            current.pressure += strength;
+            current.openings.push(Opening {
+                actor: actor.id,
+                valve: target.clone(),
+                time_remaining: new_time_remaining,
+            });
+
+            self.best_case_release(new_targets, new_actors, current);
+
+            current.pressure -= strength;
+            current.openings.pop();
+        }
+
+        // The actor can also stop here and leave the remaining valves to someone closer
+        if actors[1..].iter().any(|a| a.time_remaining > 0) {
+            actors[0].time_remaining = 0;
+            self.best_case_release(targets, actors, current);
+        }
+    }
+}
+
+/// The best release for each part, along with how much searching it took
+fn solutions(rooms: &HashMap<String, Room>) -> ((Release, SearchStats), (Release, SearchStats)) {
     // Distance from one node to another doesn't change between part 1 and part 2
     let mut distances = HashMap::new();
     (
-        best_case_release(rooms, &mut distances, "AA".to_owned(), targets.clone(), 30),
-        best_case_release_2(
-            rooms,
-            &mut distances,
-            targets,
-            vec![("AA".to_owned(), 26), ("AA".to_owned(), 26)],
-        ),
+        ValvePlan::default().best_release_with_stats(rooms, &mut distances),
+        elephant_plan().best_release_with_stats(rooms, &mut distances),
     )
 }
 
+/// The plan for part 2, where you teach an elephant to help
+fn elephant_plan() -> ValvePlan {
+    ValvePlan {
+        actors: 2,
+        training_time: 4,
+        ..Default::default()
+    }
+}
+
 fn main() -> Result<(), Error> {
-    let (part1, part2) = solutions(&parse_valve_list(&read_stdin()?)?);
-    println!("Part 1: {}", part1);
-    println!("Part 2: {}", part2);
+    let rooms = parse_valve_list(&read_stdin()?)?;
+    validate(&rooms, &ValvePlan::default().start)?;
+    let ((part1, part1_stats), (part2, part2_stats)) = solutions(&rooms);
+
+    if env::args().any(|arg| arg == "--dot") {
+        let collapse = env::args().any(|arg| arg == "--collapse");
+        let plan = ValvePlan::default();
+        let routes = plan.routes(&rooms, &part1);
+        println!("{}", to_dot(&rooms, &plan.start, collapse, Some(&routes)));
+        return Ok(());
+    }
+
+    if env::args().any(|arg| arg == "--stats") {
+        for (part, stats) in [(1, part1_stats), (2, part2_stats)] {
+            eprintln!(
+                "Part {}: explored {} nodes, pruned {}",
+                part, stats.explored, stats.pruned
+            );
+        }
+    }
+
+    println!("Part 1: {}", part1.pressure);
+    println!("Part 2: {}", part2.pressure);
+
+    if env::args().any(|arg| arg == "--transcript") {
+        println!("\n{}", ValvePlan::default().transcript(&rooms, &part1));
+        println!("\n{}", elephant_plan().transcript(&rooms, &part2));
+    }
+
     Ok(())
 }
 
@@ -273,7 +768,227 @@ mod tests {
     #[test]
     fn examples() -> Result<(), Error> {
         let rooms = parse_valve_list(SAMPLE)?;
-        assert_eq!(solutions(&rooms), (1651, 1707));
+        let ((part1, _), (part2, stats)) = solutions(&rooms);
+        assert_eq!((part1.pressure, part2.pressure), (1651, 1707));
+        assert!(stats.explored > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn team_sizes() -> Result<(), Error> {
+        let rooms = parse_valve_list(SAMPLE)?;
+        let mut distances = HashMap::new();
+        let plan = |actors| ValvePlan {
+            actors,
+            training_time: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            plan(1)
+                .best_release_with_stats(&rooms, &mut distances)
+                .0
+                .pressure,
+            1327
+        );
+        assert_eq!(
+            plan(3)
+                .best_release_with_stats(&rooms, &mut distances)
+                .0
+                .pressure,
+            1794
+        );
+        Ok(())
+    }
+
+    /// The most one actor can release from each set of valves, found without any pruning
+    fn subset_bests(rooms: &HashMap<String, Room>, plan: &ValvePlan) -> HashMap<u64, usize> {
+        let valves: Vec<&String> = rooms
+            .values()
+            .filter_map(|r| (r.flow_rate > 0).then_some(&r.name))
+            .collect();
+        let mut bests = HashMap::new();
+        let mut stack = vec![(plan.start.clone(), plan.actor_time(), 0u64, 0)];
+        while let Some((position, time, opened, pressure)) = stack.pop() {
+            let best = bests.entry(opened).or_insert(0);
+            *best = pressure.max(*best);
+
+            let distances = distances_from(position, rooms);
+            for (i, valve) in valves.iter().enumerate() {
+                if opened & (1 << i) != 0 {
+                    continue;
+                }
+                if let Some(new_time) = distances.get(*valve).and_then(|d| time.checked_sub(d + 1))
+                {
+                    stack.push((
+                        (*valve).clone(),
+                        new_time,
+                        opened | (1 << i),
+                        pressure + rooms[*valve].flow_rate * new_time,
+                    ));
+                }
+            }
+        }
+        bests
+    }
+
+    /// Split the valves between the actors in every possible way
+    fn brute_force(rooms: &HashMap<String, Room>, plan: &ValvePlan) -> usize {
+        let bests = subset_bests(rooms, plan);
+        let mut teams: HashMap<u64, usize> = HashMap::from([(0, 0)]);
+        for _ in 0..plan.actors {
+            let mut next = HashMap::new();
+            for (team_opened, team_pressure) in teams.iter() {
+                for (opened, pressure) in bests.iter() {
+                    if team_opened & opened == 0 {
+                        let best = next.entry(team_opened | opened).or_insert(0);
+                        *best = (team_pressure + pressure).max(*best);
+                    }
+                }
+            }
+            teams = next;
+        }
+        teams.into_values().max().unwrap_or(0)
+    }
+
+    #[test]
+    fn matches_brute_force() -> Result<(), Error> {
+        let sample = parse_valve_list(SAMPLE)?;
+        let split = parse_valve_list(
+            "Valve AA has flow rate=0; tunnels lead to valves BB, CC\n\
+             Valve BB has flow rate=22; tunnels lead to valves EE, DD, AA\n\
+             Valve CC has flow rate=16; tunnels lead to valves DD, FF, AA\n\
+             Valve DD has flow rate=0; tunnels lead to valves CC, FF, BB\n\
+             Valve EE has flow rate=13; tunnels lead to valves BB, GG\n\
+             Valve FF has flow rate=0; tunnels lead to valves DD, CC\n\
+             Valve GG has flow rate=2; tunnel leads to valve EE",
+        )?;
+
+        for (rooms, time, training_time) in [(&sample, 30, 4), (&split, 11, 0)] {
+            for actors in 1..=3 {
+                let plan = ValvePlan {
+                    time,
+                    actors,
+                    training_time,
+                    ..Default::default()
+                };
+                let release = plan.best_release_with_stats(rooms, &mut HashMap::new()).0;
+                assert_eq!(release.pressure, brute_force(rooms, &plan));
+            }
+        }
+
+        let two = ValvePlan {
+            time: 11,
+            actors: 2,
+            training_time: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            two.best_release_with_stats(&split, &mut HashMap::new())
+                .0
+                .pressure,
+            443
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn plan_reconstruction() -> Result<(), Error> {
+        let rooms = parse_valve_list(SAMPLE)?;
+        let plan = ValvePlan::default();
+        let release = plan.best_release_with_stats(&rooms, &mut HashMap::new()).0;
+
+        let schedules = plan.schedule(&rooms, &release);
+        let opened: Vec<(&str, usize)> = schedules[0]
+            .iter()
+            .map(|step| (step.valve.as_str(), step.minute))
+            .collect();
+        assert_eq!(
+            opened,
+            vec![
+                ("DD", 2),
+                ("BB", 5),
+                ("JJ", 9),
+                ("HH", 17),
+                ("EE", 21),
+                ("CC", 24)
+            ]
+        );
+
+        let transcript = plan.transcript(&rooms, &release);
+        assert!(transcript.starts_with(
+            "== Minute 1 ==\nNo valves are open.\nYou move to valve DD.\n\n\
+             == Minute 2 ==\nNo valves are open.\nYou open valve DD.\n\n\
+             == Minute 3 ==\nValve DD is open, releasing 20 pressure.\nYou move to valve CC."
+        ));
+        assert!(transcript.contains(
+            "== Minute 30 ==\nValves BB, CC, DD, EE, HH, and JJ are open, releasing 81 pressure."
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn dot_export() -> Result<(), Error> {
+        let rooms = parse_valve_list(SAMPLE)?;
+        let plan = ValvePlan::default();
+
+        let full = to_dot(&rooms, &plan.start, false, None);
+        assert!(full.starts_with("graph valves {\n    AA [label=\"AA (0)\"];"));
+        assert!(full.contains("\n    FF -- GG;\n"));
+
+        let collapsed = to_dot(&rooms, &plan.start, true, None);
+        assert!(!collapsed.contains("FF"));
+        assert!(collapsed.contains("\n    EE -- HH [label=\"3\"];\n"));
+
+        let release = plan.best_release_with_stats(&rooms, &mut HashMap::new()).0;
+        let routes = plan.routes(&rooms, &release);
+        let highlighted = to_dot(&rooms, &plan.start, true, Some(&routes));
+        assert!(highlighted.contains("\n    JJ [label=\"JJ (21)\", color=red];\n"));
+        assert!(highlighted.contains("\n    AA -- JJ [label=\"2\", color=red, penwidth=2];\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn pruning() -> Result<(), Error> {
+        let rooms = parse_valve_list(SAMPLE)?;
+        let (release, stats) = elephant_plan().best_release_with_stats(&rooms, &mut HashMap::new());
+        assert_eq!(release.pressure, 1707);
+        assert!(stats.pruned > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn validation() -> Result<(), Error> {
+        validate(&parse_valve_list(SAMPLE)?, "AA")?;
+
+        let rooms = parse_valve_list(
+            "Valve AA has flow rate=0; tunnels lead to valves BB, ZZ\n\
+             Valve BB has flow rate=5; tunnel leads to valve AA\n\
+             Valve CC has flow rate=7; tunnel leads to valve AA",
+        )?;
+        let error = validate(&rooms, "AA").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Invalid valve list:\n\
+             Valve AA has a tunnel to undefined valve ZZ\n\
+             Valve CC has a tunnel to valve AA with no tunnel back\n\
+             Valve CC can't be reached from AA"
+        );
+
+        // The search should still skip what it can't reach
+        let release = ValvePlan::default()
+            .best_release_with_stats(&rooms, &mut HashMap::new())
+            .0;
+        assert_eq!(release.pressure, 5 * 28);
+        let lost = ValvePlan {
+            start: "QQ".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            lost.best_release_with_stats(&rooms, &mut HashMap::new())
+                .0
+                .pressure,
+            0
+        );
         Ok(())
     }
 }